@@ -1,7 +1,23 @@
 use std::env;
-use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+
+// Gamma exponent of the brightness curve, override with MINIPOV_GAMMA=<exponent>
+// The RGB mode has one curve per colour, MINIPOV_GAMMA_RED, _GREEN and _BLUE default to MINIPOV_GAMMA
+const DEFAULT_GAMMA: f64 = 2.8;
+// Number of daisy chained TLC59711 (12 rows each), override with MINIPOV_DRIVERS=<n>
+const DEFAULT_DRIVERS: usize = 1;
+// Columns around the cylinder, override with MINIPOV_COLS=<n>
+// The images get resampled to cols x the rows of the canvas: 12 leds per driver,
+// with the rgb feature 4 RGB pixels per driver
+const DEFAULT_COLS: usize = 128;
+const ROWS_PER_DRIVER: usize = 12;
+const RGB_PIXELS_PER_DRIVER: usize = 4;
+// grayscale images that get converted into flash images, see convert_assets
+const ASSETS: &str = "assets";
+// max. stretch of an image in either direction before it gets reported as distorted
+const MAX_STRETCH: f64 = 1.5;
 
 fn main() {
     // Put the linker script somewhere the linker can find it
@@ -12,7 +28,322 @@ fn main() {
         .unwrap();
     println!("cargo:rustc-link-search={}", out.display());
 
-    // Only re-run the build script when memory.x is changed,
+    // Generate the 8 bit brightness to 16 bit PWM tables
+    let exponent = gamma_exponent("MINIPOV_GAMMA", DEFAULT_GAMMA);
+    let mut gamma = File::create(out.join("gamma.rs")).unwrap();
+    writeln!(gamma, "pub const GAMMA_EXPONENT: f32 = {:?};", exponent).unwrap();
+    write_table(&mut gamma, "GAMMA", |i| (i / 255.0).powf(exponent));
+    write_table(&mut gamma, "CIE_LIGHTNESS", |i| cie_lightness(i / 255.0 * 100.0));
+    for colour in ["RED", "GREEN", "BLUE"].iter() {
+        let var = format!("MINIPOV_GAMMA_{}", colour);
+        let exponent = gamma_exponent(&var, exponent);
+        write_table(&mut gamma, &format!("GAMMA_{}", colour), |i| (i / 255.0).powf(exponent));
+        println!("cargo:rerun-if-env-changed={}", var);
+    }
+
+    let drivers = positive_number("MINIPOV_DRIVERS", DEFAULT_DRIVERS);
+    File::create(out.join("drivers.rs"))
+        .unwrap()
+        .write_all(drivers.to_string().as_bytes())
+        .unwrap();
+    let cols = positive_number("MINIPOV_COLS", DEFAULT_COLS);
+    File::create(out.join("cols.rs"))
+        .unwrap()
+        .write_all(cols.to_string().as_bytes())
+        .unwrap();
+
+    let rows_per_driver = if env::var_os("CARGO_FEATURE_RGB").is_some() {
+        RGB_PIXELS_PER_DRIVER
+    } else {
+        ROWS_PER_DRIVER
+    };
+    convert_assets(Path::new(ASSETS), &out.join("assets.rs"), cols, rows_per_driver * drivers);
+
+    // Only re-run the build script when memory.x, the gamma exponent, the driver or column count or the assets are changed,
     // instead of when any part of the source code changes.
     println!("cargo:rerun-if-changed=memory.x");
+    println!("cargo:rerun-if-changed={}", ASSETS);
+    println!("cargo:rerun-if-env-changed=MINIPOV_GAMMA");
+    println!("cargo:rerun-if-env-changed=MINIPOV_DRIVERS");
+    println!("cargo:rerun-if-env-changed=MINIPOV_COLS");
+}
+
+fn positive_number(var: &str, default: usize) -> usize {
+    match env::var(var) {
+        Ok(val) => val
+            .parse::<usize>()
+            .ok()
+            .filter(|n| *n > 0)
+            .unwrap_or_else(|| panic!("{}={} is not a positive number", var, val)),
+        Err(_) => default,
+    }
+}
+
+fn gamma_exponent(var: &str, default: f64) -> f64 {
+    match env::var(var) {
+        Ok(val) => val
+            .parse::<f64>()
+            .ok()
+            .filter(|e| *e > 0.0)
+            .unwrap_or_else(|| panic!("{}={} is not a positive number", var, val)),
+        Err(_) => default,
+    }
+}
+
+// var gamma = new Uint16Array(256);
+// for (var i=0.0;i<256;i=i+1.0) gamma[i]=Math.round(Math.pow(i/255.0,2.8)*65535);
+fn write_table(out: &mut File, name: &str, curve: impl Fn(f64) -> f64) {
+    writeln!(out, "pub const {}: [u16; 256] = [", name).unwrap();
+    for row in 0..32 {
+        let vals: Vec<String> = (0..8)
+            .map(|i| ((curve((row * 8 + i) as f64) * 65535.0).round() as u16).to_string())
+            .collect();
+        writeln!(out, "    {},", vals.join(", ")).unwrap();
+    }
+    writeln!(out, "];").unwrap();
+}
+
+// CIE 1931 lightness L* (0..100) to relative luminance (0..1)
+fn cie_lightness(l: f64) -> f64 {
+    if l <= 8.0 {
+        l / 903.3
+    } else {
+        ((l + 16.0) / 116.0).powi(3)
+    }
+}
+
+// Turn every PNG, PGM and PBM file in dir into a minipov::image::Image constant
+// named like the file (assets/wave.pgm becomes WAVE), resampled to cols x rows.
+// Light pixels are lit, draw on black. The build reports images that get stretched or squeezed
+// by more than MAX_STRETCH and images smaller than cols x rows, they get scaled up and blurry.
+fn convert_assets(dir: &Path, out: &Path, cols: usize, rows: usize) {
+    let mut paths: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries.map(|entry| entry.unwrap().path()).collect(),
+        // no assets
+        Err(_) => Vec::new(),
+    };
+    paths.sort();
+    let mut assets = File::create(out).unwrap();
+    let mut names = Vec::new();
+    for path in paths {
+        let ext = path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase());
+        let (width, height, pixels) = match ext.as_deref() {
+            Some("png") => read_png(&path),
+            Some("pgm") | Some("pbm") => read_netpbm(&path),
+            _ => continue,
+        }
+        .and_then(|(width, height, pixels)| {
+            if width == 0 || height == 0 {
+                Err(format!("{}x{} has no pixels", width, height))
+            } else {
+                Ok((width, height, pixels))
+            }
+        })
+        .unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+        println!("cargo:rerun-if-changed={}", path.display());
+
+        let stretch = (cols as f64 / width as f64) / (rows as f64 / height as f64);
+        if !(1.0 / MAX_STRETCH..=MAX_STRETCH).contains(&stretch) {
+            println!(
+                "cargo:warning={}: {}x{} gets {} {:.1} times to fit the cylinder ({}x{})",
+                path.display(),
+                width,
+                height,
+                if stretch > 1.0 { "stretched" } else { "squeezed" },
+                if stretch > 1.0 { stretch } else { 1.0 / stretch },
+                cols,
+                rows
+            );
+        }
+        if width < cols || height < rows {
+            println!(
+                "cargo:warning={}: {}x{} is smaller than the cylinder ({}x{}), it gets scaled up",
+                path.display(),
+                width,
+                height,
+                cols,
+                rows
+            );
+        }
+        let pixels = resample(&pixels, width, height, cols, rows);
+
+        let stem = path.file_stem().unwrap().to_string_lossy();
+        let name: String = stem
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect();
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic()) || names.contains(&name) {
+            panic!("{}: {} is not a usable constant name or taken", path.display(), name);
+        }
+        writeln!(assets, "// {}, {}x{} pixels", path.display(), width, height).unwrap();
+        writeln!(assets, "pub const {}: Image = Image {{", name).unwrap();
+        writeln!(assets, "    cols: {},\n    rows: {},\n    pixels: &[", cols, rows).unwrap();
+        // one column per line
+        for col in pixels.chunks(rows) {
+            let vals: Vec<String> = col.iter().map(|v| v.to_string()).collect();
+            writeln!(assets, "        {},", vals.join(", ")).unwrap();
+        }
+        writeln!(assets, "    ],\n}};").unwrap();
+        names.push(name);
+    }
+    writeln!(assets, "pub const IMAGES: [(&str, &Image); {}] = [", names.len()).unwrap();
+    for name in &names {
+        writeln!(assets, "    (\"{}\", &{}),", name, name).unwrap();
+    }
+    writeln!(assets, "];").unwrap();
+}
+
+// Row-major brightness (0..=255) of a PNG, colours become their luma, transparent pixels black
+fn read_png(path: &Path) -> Result<(usize, usize, Vec<u8>), String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let (info, mut reader) = decoder.read_info().map_err(|e| e.to_string())?;
+    let mut buf = vec![0; info.buffer_size()];
+    reader.next_frame(&mut buf).map_err(|e| e.to_string())?;
+    let (width, height) = (info.width as usize, info.height as usize);
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::RGB => 3,
+        png::ColorType::RGBA => 4,
+        png::ColorType::Indexed => return Err("palette didn't get expanded".to_string()),
+    };
+    let mut pixels = Vec::with_capacity(width * height);
+    for line in buf.chunks(info.line_size) {
+        for px in line[..width * channels].chunks(channels) {
+            let (value, alpha) = match px {
+                [v] => (*v as f64, 255.0),
+                [v, a] => (*v as f64, *a as f64),
+                [r, g, b] => (luma(*r, *g, *b), 255.0),
+                [r, g, b, a] => (luma(*r, *g, *b), *a as f64),
+                _ => unreachable!(),
+            };
+            pixels.push((value * alpha / 255.0).round() as u8);
+        }
+    }
+    Ok((width, height, pixels))
+}
+
+fn luma(r: u8, g: u8, b: u8) -> f64 {
+    0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64
+}
+
+// Row-major brightness (0..=255) of a PGM (P2, P5) or PBM (P1, P4), a set PBM bit is black
+fn read_netpbm(path: &Path) -> Result<(usize, usize, Vec<u8>), String> {
+    let data = fs::read(path).map_err(|e| e.to_string())?;
+    let mut pos = 0;
+    // the next whitespace separated header field, comments start with #
+    let mut field = || -> Result<String, String> {
+        loop {
+            while pos < data.len() && data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if pos < data.len() && data[pos] == b'#' {
+                while pos < data.len() && data[pos] != b'\n' {
+                    pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+        let start = pos;
+        while pos < data.len() && !data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return Err("file ends early".to_string());
+        }
+        Ok(String::from_utf8_lossy(&data[start..pos]).into_owned())
+    };
+    let number = |s: String| s.parse::<usize>().map_err(|_| format!("{} is not a number", s));
+    let magic = field()?;
+    let width = number(field()?)?;
+    let height = number(field()?)?;
+    let max = if magic == "P1" || magic == "P4" { 1 } else { number(field()?)? };
+    if max == 0 || max > 65535 {
+        return Err(format!("max. value {} out of range", max));
+    }
+    let scale = |v: usize| (v.min(max) * 255 + max / 2) / max;
+    let count = width * height;
+    let pixels: Vec<u8> = match magic.as_str() {
+        // plain formats: numbers separated by whitespace, P1 digits may touch
+        "P1" => {
+            let mut bits = Vec::with_capacity(count);
+            while bits.len() < count {
+                for c in field()?.chars() {
+                    bits.push(if c == '0' { 255 } else { 0 });
+                }
+            }
+            bits.truncate(count);
+            bits
+        }
+        "P2" => (0..count).map(|_| Ok(scale(number(field()?)?) as u8)).collect::<Result<_, String>>()?,
+        // binary formats: one whitespace after the header
+        "P4" | "P5" => {
+            let raster = &data[(pos + 1).min(data.len())..];
+            if magic == "P4" {
+                // usize::div_ceil needs a much newer Rust than the rest of the tree
+                #[allow(clippy::manual_div_ceil)]
+                let stride = (width + 7) / 8;
+                if raster.len() < stride * height {
+                    return Err("file ends early".to_string());
+                }
+                (0..count)
+                    .map(|i| {
+                        let (x, y) = (i % width, i / width);
+                        if raster[y * stride + x / 8] & (0x80 >> (x % 8)) != 0 { 0 } else { 255 }
+                    })
+                    .collect()
+            } else {
+                let bytes = if max > 255 { 2 } else { 1 };
+                if raster.len() < count * bytes {
+                    return Err("file ends early".to_string());
+                }
+                raster
+                    .chunks(bytes)
+                    .take(count)
+                    .map(|b| scale(b.iter().fold(0, |v, &b| v << 8 | b as usize)) as u8)
+                    .collect()
+            }
+        }
+        _ => return Err(format!("{} is no PGM or PBM", magic)),
+    };
+    Ok((width, height, pixels))
+}
+
+// Area average of row-major width x height pixels onto a column-major cols x rows grid
+fn resample(pixels: &[u8], width: usize, height: usize, cols: usize, rows: usize) -> Vec<u8> {
+    let x_weights = weights(width, cols);
+    let y_weights = weights(height, rows);
+    let mut out = Vec::with_capacity(cols * rows);
+    for xw in &x_weights {
+        for yw in &y_weights {
+            let mut sum = 0.0;
+            for &(y, wy) in yw {
+                for &(x, wx) in xw {
+                    sum += pixels[y * width + x] as f64 * wx * wy;
+                }
+            }
+            out.push(sum.round().min(255.0) as u8);
+        }
+    }
+    out
+}
+
+// The source pixels under each of the n destination pixels and how much of it they cover, adding up to 1
+fn weights(len: usize, n: usize) -> Vec<Vec<(usize, f64)>> {
+    let scale = len as f64 / n as f64;
+    (0..n)
+        .map(|i| {
+            let (start, end) = (i as f64 * scale, (i + 1) as f64 * scale);
+            (start.floor() as usize..(end.ceil() as usize).min(len))
+                .map(|j| {
+                    let cover = end.min(j as f64 + 1.0) - start.max(j as f64);
+                    (j, cover / scale)
+                })
+                .filter(|&(_, w)| w > 0.0)
+                .collect()
+        })
+        .collect()
 }
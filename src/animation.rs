@@ -0,0 +1,252 @@
+// Animations: a sequence of flash images, each shown for a number of revolutions or milliseconds.
+// The player keeps its own time, idle asks it for the current frame whenever it renders a buffer,
+// so frames don't get skipped or stretched when a buffer gets repeated.
+// Frame changes are scheduled from the previous change, not from when idle got around to it,
+// so the timing doesn't drift.
+
+use crate::canvas::{Canvas, Dim};
+use crate::image::Image;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Duration {
+    // revolutions of the fan, the frame holds while the fan stands still
+    Revolutions(u32),
+    Ms(u32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frame {
+    pub image: &'static Image,
+    pub duration: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    // 0 1 2 0 1 2 ...
+    Loop,
+    // 0 1 2 1 0 1 ...
+    PingPong,
+    // 0 1 2 and the last frame stays
+    OneShot,
+}
+
+pub struct Animation {
+    pub frames: &'static [Frame],
+    pub mode: Mode,
+}
+
+pub struct Player {
+    animation: &'static Animation,
+    // frequency of the timestamps passed to advance
+    tick_hz: u32,
+    index: usize,
+    // ping-pong direction
+    backwards: bool,
+    finished: bool,
+    // timestamp and revolution count when the current frame started, None before the first advance
+    start: Option<(u32, u32)>,
+}
+
+impl Player {
+    pub fn new(animation: &'static Animation, tick_hz: u32) -> Self {
+        assert!(!animation.frames.is_empty(), "animation without frames");
+        Player {
+            animation,
+            tick_hz,
+            index: 0,
+            backwards: false,
+            finished: false,
+            start: None,
+        }
+    }
+
+    // start over with the first frame
+    pub fn restart(&mut self) {
+        self.index = 0;
+        self.backwards = false;
+        self.finished = false;
+        self.start = None;
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn frame(&self) -> &'static Frame {
+        &self.animation.frames[self.index]
+    }
+
+    // a one-shot animation reached its last frame and that one is over
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    // Step through the frames that are over at timestamp now (wrapping tick counter)
+    // with revolutions (wrapping) revolutions of the fan so far
+    pub fn advance(&mut self, now: u32, revolutions: u32) {
+        let (mut start, mut start_revs) = match self.start {
+            Some(start) => start,
+            None => {
+                self.start = Some((now, revolutions));
+                return;
+            }
+        };
+        // one pass through the frames at most, after a long pause the current frame starts now
+        for _ in 0..self.animation.frames.len() {
+            if self.finished {
+                return;
+            }
+            match self.frame().duration {
+                Duration::Ms(ms) => {
+                    let ticks = (ms as u64 * self.tick_hz as u64 / 1000) as u32;
+                    if now.wrapping_sub(start) < ticks {
+                        return;
+                    }
+                    start = start.wrapping_add(ticks);
+                    start_revs = revolutions;
+                }
+                Duration::Revolutions(revs) => {
+                    if revolutions.wrapping_sub(start_revs) < revs {
+                        return;
+                    }
+                    start_revs = start_revs.wrapping_add(revs);
+                    start = now;
+                }
+            }
+            self.step();
+            self.start = Some((start, start_revs));
+        }
+        self.start = Some((now, revolutions));
+    }
+
+    fn step(&mut self) {
+        let last = self.animation.frames.len() - 1;
+        match self.animation.mode {
+            Mode::Loop => self.index = if self.index == last { 0 } else { self.index + 1 },
+            Mode::OneShot => {
+                if self.index == last {
+                    self.finished = true;
+                } else {
+                    self.index += 1;
+                }
+            }
+            Mode::PingPong => {
+                if last == 0 {
+                    return;
+                }
+                if self.index == last {
+                    self.backwards = true;
+                } else if self.index == 0 {
+                    self.backwards = false;
+                }
+                self.index = if self.backwards { self.index - 1 } else { self.index + 1 };
+            }
+        }
+    }
+
+    // Fill a whole canvas with the frame at timestamp now
+    pub fn render<C: Canvas>(&mut self, canvas: &mut C, now: u32, revolutions: u32, color: C::Color)
+    where
+        C::Color: Dim,
+    {
+        self.advance(now, revolutions);
+        self.frame().image.draw(canvas, color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HZ: u32 = 1000;
+
+    const IMAGE: Image = Image {
+        cols: 1,
+        rows: 1,
+        pixels: &[255],
+    };
+
+    const fn frame(duration: Duration) -> Frame {
+        Frame { image: &IMAGE, duration }
+    }
+
+    const FRAMES: [Frame; 3] = [
+        frame(Duration::Ms(100)),
+        frame(Duration::Ms(200)),
+        frame(Duration::Revolutions(2)),
+    ];
+
+    static LOOP: Animation = Animation { frames: &FRAMES, mode: Mode::Loop };
+    static PING_PONG: Animation = Animation { frames: &FRAMES, mode: Mode::PingPong };
+    static ONE_SHOT: Animation = Animation { frames: &FRAMES, mode: Mode::OneShot };
+
+    // the frame index every 50 ms for a second, one revolution every 100 ms
+    fn indices(animation: &'static Animation, start: u32) -> Vec<usize> {
+        let mut player = Player::new(animation, HZ);
+        (0..20u32)
+            .map(|i| {
+                let now = start.wrapping_add(i * 50);
+                player.advance(now, i / 2);
+                player.index()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn loop_mode() {
+        assert_eq!(
+            indices(&LOOP, 0),
+            [0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2]
+        );
+    }
+
+    #[test]
+    fn ping_pong_mode() {
+        assert_eq!(
+            indices(&PING_PONG, 0),
+            [0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 1, 1, 1, 1, 0, 0, 1, 1, 1, 1]
+        );
+    }
+
+    #[test]
+    fn one_shot_mode() {
+        let mut player = Player::new(&ONE_SHOT, HZ);
+        assert_eq!(indices(&ONE_SHOT, 0)[8..], [2; 12]);
+        for i in 0..20u32 {
+            player.advance(i * 50, i / 2);
+        }
+        assert!(player.finished());
+        player.restart();
+        assert_eq!((player.index(), player.finished()), (0, false));
+    }
+
+    #[test]
+    fn tick_counter_wraparound() {
+        assert_eq!(indices(&LOOP, u32::MAX - 120), indices(&LOOP, 0));
+    }
+
+    #[test]
+    fn holds_while_the_fan_stands_still() {
+        let mut player = Player::new(&LOOP, HZ);
+        player.advance(0, 0);
+        player.advance(300, 0);
+        assert_eq!(player.index(), 2);
+        player.advance(10_000, 1);
+        assert_eq!(player.index(), 2);
+        player.advance(10_001, 2);
+        assert_eq!(player.index(), 0);
+    }
+
+    #[test]
+    fn no_drift_with_late_renders() {
+        let mut player = Player::new(&LOOP, HZ);
+        player.advance(0, 0);
+        // idle was late for the first change, the second one comes on time anyway
+        player.advance(150, 0);
+        assert_eq!(player.index(), 1);
+        player.advance(299, 0);
+        assert_eq!(player.index(), 1);
+        player.advance(300, 0);
+        assert_eq!(player.index(), 2);
+    }
+}
@@ -0,0 +1,25 @@
+// The images build.rs converted from the assets directory, COLS x the rows of the canvas each:
+// ROWS leds, or RGB_ROWS pixels with the rgb feature.
+// IMAGES lists them all with their names.
+
+use crate::image::Image;
+
+include!(concat!(env!("OUT_DIR"), "/assets.rs"));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dmabuffer::COLS;
+    #[cfg(not(feature = "rgb"))]
+    use crate::dmabuffer::ROWS;
+    #[cfg(feature = "rgb")]
+    use crate::rgb::RGB_ROWS as ROWS;
+
+    #[test]
+    fn images_fill_the_cylinder() {
+        for (name, image) in IMAGES.iter() {
+            assert_eq!((image.cols, image.rows), (COLS, ROWS), "{}", name);
+            assert_eq!(image.pixels.len(), COLS * ROWS, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+// Host side of the binary ITM log: reads the raw ITM stream openocd writes
+// (the .vscode/itm.log fifo, or a captured file) and prints
+// the text of stimulus port 0 and the decoded log records of the module ports.
+//
+// cargo run --bin itmdecode --features decoder --target x86_64-unknown-linux-gnu -- .vscode/itm.log
+//
+// --hz sets the DWT clock for the timestamps, the core clock of the firmware (84 MHz).
+// --module <name> only prints the port of that module, repeat it for more modules.
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process;
+
+use minipov::log::{self, ItmParser, Module, PortDecoder};
+
+const USAGE: &str = "usage: itmdecode [--hz <DWT clock>] [--module <name>]... <itm file>";
+
+fn main() {
+    let mut hz = 84_000_000u32;
+    let mut path = None;
+    let mut ports = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--hz" {
+            hz = args.next().and_then(|v| v.parse().ok()).unwrap_or_else(|| usage());
+        } else if arg == "--module" {
+            let module = args.next().and_then(|v| module(&v)).unwrap_or_else(|| usage());
+            ports.push(log::port(module));
+        } else if path.is_none() {
+            path = Some(arg);
+        } else {
+            usage();
+        }
+    }
+    let path = path.unwrap_or_else(|| usage());
+    let file = File::open(&path).unwrap_or_else(|err| {
+        eprintln!("{}: {}", path, err);
+        process::exit(1);
+    });
+    if ports.is_empty() {
+        ports = Module::ALL.iter().map(|&module| log::port(module)).collect();
+    }
+    if let Err(err) = decode(BufReader::new(file), hz, &ports, &mut io::stdout()) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+// the module of a name as printed in the records, any case
+fn module(name: &str) -> Option<Module> {
+    Module::ALL.iter().copied().find(|module| format!("{:?}", module).eq_ignore_ascii_case(name))
+}
+
+// ports lists the log ports to print
+fn decode<R: BufRead, W: Write>(input: R, hz: u32, ports: &[usize], out: &mut W) -> io::Result<()> {
+    let mut parser = ItmParser::new();
+    let mut decoder = PortDecoder::new();
+    // port 0 text up to the next newline
+    let mut line = Vec::new();
+    for byte in input.bytes() {
+        let packet = match parser.feed(byte?) {
+            Some(packet) => packet,
+            None => continue,
+        };
+        if packet.port == 0 {
+            for &c in &packet.value.to_le_bytes()[..packet.size] {
+                if c == b'\n' {
+                    writeln!(out, "{}", String::from_utf8_lossy(&line))?;
+                    line.clear();
+                } else {
+                    line.push(c);
+                }
+            }
+        } else if ports.contains(&packet.port) {
+            let skipped = decoder.skipped();
+            if let Some(record) = decoder.feed(&packet) {
+                let mut text = String::new();
+                log::write_record(&mut text, &record).unwrap();
+                let secs = record.timestamp as f64 / hz as f64;
+                writeln!(out, "[{:10.6}] {}", secs, text)?;
+            } else if decoder.skipped() != skipped && skipped == 0 {
+                eprintln!("lost log words, waiting for the next record");
+            }
+        }
+        out.flush()?;
+    }
+    if decoder.skipped() > 0 {
+        eprintln!("{} log words skipped", decoder.skipped());
+    }
+    Ok(())
+}
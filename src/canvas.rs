@@ -0,0 +1,107 @@
+// Something the renderers (text, marquee ...) can draw into.
+// A DMAbuffer is a monochrome canvas with one row per driver output,
+// rgb::RgbCanvas views the same buffer as RGB pixels.
+// A canvas is COLS columns wide unless it's a view on part of the buffer.
+
+use crate::dmabuffer::COLS;
+
+pub trait Canvas {
+    type Color: Copy;
+    // colour of a pixel that is off
+    const OFF: Self::Color;
+
+    fn rows(&self) -> usize;
+    fn cols(&self) -> usize {
+        COLS
+    }
+    fn put_pixel(&mut self, col: usize, row: usize, color: Self::Color);
+    // switch all pixels of a column off and write its command words
+    fn clear_col(&mut self, col: usize);
+}
+
+// Colours that can be dimmed by a brightness (0..=255), 255 keeps the colour.
+// Grayscale images get drawn in a colour this way.
+pub trait Dim: Copy {
+    fn dim(self, brightness: u8) -> Self;
+}
+
+// the brightness scales linearly, the gamma curve comes after
+impl Dim for u8 {
+    fn dim(self, brightness: u8) -> u8 {
+        ((self as u16 * brightness as u16 + 127) / 255) as u8
+    }
+}
+
+// One half of a canvas, half 0 is the first half-turn after the image start, half 1 the second.
+// Draws different content on each half-turn of a single image.
+pub struct Half<'a, C: Canvas> {
+    canvas: &'a mut C,
+    start: usize,
+}
+
+impl<'a, C: Canvas> Half<'a, C> {
+    pub fn new(canvas: &'a mut C, half: usize) -> Self {
+        let start = (half % 2) * (canvas.cols() / 2);
+        Half { canvas, start }
+    }
+}
+
+impl<'a, C: Canvas> Canvas for Half<'a, C> {
+    type Color = C::Color;
+    const OFF: C::Color = C::OFF;
+
+    fn rows(&self) -> usize {
+        self.canvas.rows()
+    }
+
+    fn cols(&self) -> usize {
+        self.canvas.cols() / 2
+    }
+
+    fn put_pixel(&mut self, col: usize, row: usize, color: C::Color) {
+        if col < self.cols() {
+            self.canvas.put_pixel(self.start + col, row, color);
+        }
+    }
+
+    fn clear_col(&mut self, col: usize) {
+        if col < self.cols() {
+            self.canvas.clear_col(self.start + col);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dmabuffer::DMAbuffer;
+    use crate::font::{draw_text, FONT_5X7};
+
+    #[test]
+    fn dim_keeps_the_ends() {
+        assert_eq!(200u8.dim(255), 200);
+        assert_eq!(200u8.dim(0), 0);
+        assert_eq!(255u8.dim(128), 128);
+    }
+
+    #[test]
+    fn halves_split_the_columns() {
+        let mut buf = DMAbuffer::new();
+        let mut second = Half::new(&mut buf, 1);
+        assert_eq!(second.cols(), COLS / 2);
+        second.put_pixel(0, 0, 255);
+        // clipped at the end of the half
+        second.put_pixel(COLS / 2, 1, 255);
+        assert_eq!(buf.pwm(COLS / 2, 0), 0xFFFF);
+        assert!((0..COLS).all(|col| buf.pwm(col, 1) == 0));
+    }
+
+    #[test]
+    fn text_gets_clipped_at_the_half() {
+        let mut buf = DMAbuffer::new();
+        let mut first = Half::new(&mut buf, 0);
+        let drawn = draw_text(&mut first, &FONT_5X7, COLS / 2 - 3, "MM", 255);
+        assert_eq!(drawn, 3);
+        assert!((COLS / 2..COLS).all(|col| (0..12).all(|row| buf.pwm(col, row) == 0)));
+    }
+}
@@ -0,0 +1,310 @@
+// Clock tree of the STM32F401/F411: HSE crystal (or HSI) -> PLL -> SYSCLK -> AHB/APB prescalers.
+// The divisors are plain numbers, clocksetup turns them into register values.
+// Everything is const, the bus frequencies get used to derive the SPI and timer setup
+// and an illegal config fails the build:
+//   const _: [(); 0 - !CLOCKS.valid(&STM32F401) as usize] = [];
+
+const MHZ: u32 = 1_000_000;
+
+// internal RC oscillator, the fallback if the crystal doesn't start
+pub const HSI_HZ: u32 = 16 * MHZ;
+// one more flash wait state every 30 MHz of HCLK
+pub const FLASH_WAIT_STATE_HZ: u32 = 30 * MHZ;
+pub const PLL48_HZ: u32 = 48 * MHZ;
+
+// bus limits of a chip at 2.7 - 3.6 V
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Limits {
+    pub sysclk_hz: u32,
+    pub pclk1_hz: u32,
+    pub pclk2_hz: u32,
+}
+
+pub const STM32F401: Limits = Limits {
+    sysclk_hz: 84 * MHZ,
+    pclk1_hz: 42 * MHZ,
+    pclk2_hz: 84 * MHZ,
+};
+
+pub const STM32F411: Limits = Limits {
+    sysclk_hz: 100 * MHZ,
+    pclk1_hz: 50 * MHZ,
+    pclk2_hz: 100 * MHZ,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClockSource {
+    Hse,
+    Hsi,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClockConfig {
+    // PLL input: the crystal, or the HSI for the fallback
+    pub source: ClockSource,
+    pub crystal_hz: u32,
+    // PLLM, the VCO input has to be 1 - 2 MHz
+    pub crystal_divisor: u32,
+    // PLLN, the VCO output has to be 192 - 432 MHz
+    pub pll_multiplier: u32,
+    // PLLP: 2, 4, 6 or 8
+    pub general_divisor: u32,
+    // PLLQ: 2 - 15
+    pub pll48_divisor: u32,
+    // USB and SDIO need exactly 48 MHz from PLLQ, otherwise it must not be faster
+    pub pll48_exact: bool,
+
+    // 1, 2, 4, 8, 16, 64, 128, 256 or 512
+    pub ahb_divisor: u32,
+    // 1, 2, 4, 8 or 16
+    pub apb1_divisor: u32,
+    pub apb2_divisor: u32,
+
+    pub flash_latency: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frequencies {
+    pub sysclk: u32,
+    // core, DWT cycle counter and DMA
+    pub hclk: u32,
+    pub pclk1: u32,
+    pub pclk2: u32,
+    // timers run at twice the APB clock unless the APB divisor is 1
+    pub apb1_timer: u32,
+    pub apb2_timer: u32,
+    pub pll48: u32,
+}
+
+// Presets for common boards: 8 MHz (e.g. Nucleo with ST-LINK MCO) and 25 MHz crystals (black pill).
+// 84 MHz with exactly 48 MHz for USB fits the F401, 100 MHz needs an F411
+// and leaves PLL48 a bit slower since no PLLP gets 100 MHz and 48 MHz out of the same VCO.
+pub const HSE8_84MHZ: ClockConfig = ClockConfig {
+    source: ClockSource::Hse,
+    crystal_hz: 8 * MHZ,
+    crystal_divisor: 4,
+    pll_multiplier: 168,
+    general_divisor: 4,
+    pll48_divisor: 7,
+    pll48_exact: true,
+    ahb_divisor: 1,
+    apb1_divisor: 2,
+    apb2_divisor: 1,
+    flash_latency: 2,
+};
+
+pub const HSE25_84MHZ: ClockConfig = ClockConfig {
+    crystal_hz: 25 * MHZ,
+    crystal_divisor: 25,
+    pll_multiplier: 336,
+    ..HSE8_84MHZ
+};
+
+pub const HSE8_100MHZ: ClockConfig = ClockConfig {
+    pll_multiplier: 200,
+    pll48_divisor: 9,
+    pll48_exact: false,
+    flash_latency: 3,
+    ..HSE8_84MHZ
+};
+
+pub const HSE25_100MHZ: ClockConfig = ClockConfig {
+    crystal_hz: 25 * MHZ,
+    crystal_divisor: 25,
+    pll_multiplier: 400,
+    ..HSE8_100MHZ
+};
+
+impl ClockConfig {
+    // The same PLL fed from the HSI: PLLM gets scaled so the VCO input stays the same,
+    // all the derived frequencies don't change.
+    pub const fn hsi_fallback(&self) -> ClockConfig {
+        ClockConfig {
+            source: ClockSource::Hsi,
+            crystal_hz: HSI_HZ,
+            crystal_divisor: HSI_HZ / self.vco_in(),
+            ..*self
+        }
+    }
+
+    pub const fn vco_in(&self) -> u32 {
+        self.crystal_hz / self.crystal_divisor
+    }
+
+    pub const fn vco_out(&self) -> u32 {
+        self.vco_in() * self.pll_multiplier
+    }
+
+    pub const fn frequencies(&self) -> Frequencies {
+        let sysclk = self.vco_out() / self.general_divisor;
+        let hclk = sysclk / self.ahb_divisor;
+        let pclk1 = hclk / self.apb1_divisor;
+        let pclk2 = hclk / self.apb2_divisor;
+        Frequencies {
+            sysclk,
+            hclk,
+            pclk1,
+            pclk2,
+            apb1_timer: pclk1 * (1 + (self.apb1_divisor > 1) as u32),
+            apb2_timer: pclk2 * (1 + (self.apb2_divisor > 1) as u32),
+            pll48: self.vco_out() / self.pll48_divisor,
+        }
+    }
+
+    // flash wait states needed for HCLK
+    pub const fn min_flash_latency(&self) -> u32 {
+        (self.frequencies().hclk - 1) / FLASH_WAIT_STATE_HZ
+    }
+
+    // divisors the hardware supports, PLL in range, buses not overclocked and enough flash wait states
+    pub const fn valid(&self, chip: &Limits) -> bool {
+        let f = self.frequencies();
+        (self.crystal_hz >= 4 * MHZ)
+            & (self.crystal_hz <= 26 * MHZ)
+            & ((self.source as u32 == ClockSource::Hse as u32) | (self.crystal_hz == HSI_HZ))
+            & (self.crystal_divisor >= 2)
+            & (self.crystal_divisor <= 63)
+            // the VCO input divides evenly, otherwise the frequencies are off
+            & (self.vco_in() * self.crystal_divisor == self.crystal_hz)
+            & (self.vco_in() >= MHZ)
+            & (self.vco_in() <= 2 * MHZ)
+            & (self.pll_multiplier >= 50)
+            & (self.pll_multiplier <= 432)
+            & (self.vco_out() >= 192 * MHZ)
+            & (self.vco_out() <= 432 * MHZ)
+            & (pllp_bits(self.general_divisor) < 4)
+            & (self.pll48_divisor >= 2)
+            & (self.pll48_divisor <= 15)
+            & ((f.pll48 == PLL48_HZ) | (!self.pll48_exact & (f.pll48 <= PLL48_HZ)))
+            & (hpre_bits(self.ahb_divisor) < 16)
+            & (ppre_bits(self.apb1_divisor) < 8)
+            & (ppre_bits(self.apb2_divisor) < 8)
+            & (f.sysclk <= chip.sysclk_hz)
+            & (f.pclk1 <= chip.pclk1_hz)
+            & (f.pclk2 <= chip.pclk2_hz)
+            & (self.flash_latency >= self.min_flash_latency())
+            & (self.flash_latency <= 7)
+    }
+
+    // valid and the HSI fallback gives the same frequencies
+    pub const fn valid_with_fallback(&self, chip: &Limits) -> bool {
+        let hsi = self.hsi_fallback();
+        self.valid(chip) & hsi.valid(chip) & (hsi.vco_in() == self.vco_in())
+    }
+}
+
+// Register encodings, an unsupported divisor gives an out of range value.
+// PLLP: 2 -> 0, 4 -> 1, 6 -> 2, 8 -> 3
+pub const fn pllp_bits(div: u32) -> u32 {
+    (div / 2).wrapping_sub(1) | (4 * (div & 1))
+}
+
+// HPRE: 1 -> 0, 2 -> 8, 4 -> 9 ... 16 -> 11, 64 -> 12 ... 512 -> 15, no 32
+pub const fn hpre_bits(div: u32) -> u32 {
+    let shift = div.trailing_zeros();
+    let power_of_2 = div.is_power_of_two() as u32;
+    let bits = (div > 1) as u32 * (7 + shift - (shift > 5) as u32);
+    bits + 16 * (1 - power_of_2) + 16 * (div == 32) as u32 + 16 * (div > 512) as u32
+}
+
+// PPRE1/PPRE2: 1 -> 0, 2 -> 4, 4 -> 5, 8 -> 6, 16 -> 7
+pub const fn ppre_bits(div: u32) -> u32 {
+    let shift = div.trailing_zeros();
+    let power_of_2 = div.is_power_of_two() as u32;
+    (div > 1) as u32 * (3 + shift) + 8 * (1 - power_of_2) + 8 * (div > 16) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the original setup: 8 MHz crystal, 84 MHz
+    const CONFIG: ClockConfig = HSE8_84MHZ;
+    const F401: &Limits = &STM32F401;
+
+    #[test]
+    fn frequencies() {
+        assert!(CONFIG.valid(F401));
+        assert_eq!(
+            CONFIG.frequencies(),
+            Frequencies {
+                sysclk: 84 * MHZ,
+                hclk: 84 * MHZ,
+                pclk1: 42 * MHZ,
+                pclk2: 84 * MHZ,
+                apb1_timer: 84 * MHZ,
+                apb2_timer: 84 * MHZ,
+                pll48: 48 * MHZ,
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_illegal_configs() {
+        // VCO input 4 MHz
+        assert!(!ClockConfig { crystal_divisor: 2, pll_multiplier: 84, ..CONFIG }.valid(F401));
+        // APB1 at 84 MHz
+        assert!(!ClockConfig { apb1_divisor: 1, ..CONFIG }.valid(F401));
+        // SYSCLK 168 MHz
+        assert!(!ClockConfig { general_divisor: 2, ..CONFIG }.valid(F401));
+        // PLL48 at 56 MHz
+        assert!(!ClockConfig { pll48_divisor: 6, ..CONFIG }.valid(F401));
+        // 42 MHz from PLL48 is fine without USB
+        assert!(!ClockConfig { pll48_divisor: 8, ..CONFIG }.valid(F401));
+        assert!(ClockConfig { pll48_divisor: 8, pll48_exact: false, ..CONFIG }.valid(F401));
+        // too few wait states
+        assert!(!ClockConfig { flash_latency: 1, ..CONFIG }.valid(F401));
+        // PLLP 3 and APB divisor 3 don't exist
+        assert!(!ClockConfig { general_divisor: 3, ..CONFIG }.valid(F401));
+        assert!(!ClockConfig { apb2_divisor: 3, ..CONFIG }.valid(F401));
+        assert!(!ClockConfig { ahb_divisor: 32, ..CONFIG }.valid(F401));
+    }
+
+    #[test]
+    fn presets() {
+        for preset in &[HSE8_84MHZ, HSE25_84MHZ] {
+            assert!(preset.valid_with_fallback(F401));
+            assert_eq!(preset.frequencies(), CONFIG.frequencies());
+        }
+        for preset in &[HSE8_100MHZ, HSE25_100MHZ] {
+            assert!(!preset.valid(F401));
+            assert!(preset.valid_with_fallback(&STM32F411));
+            let f = preset.frequencies();
+            assert_eq!((f.hclk, f.pclk1, f.pclk2, f.apb1_timer), (100 * MHZ, 50 * MHZ, 100 * MHZ, 100 * MHZ));
+            assert!(f.pll48 < PLL48_HZ);
+        }
+    }
+
+    #[test]
+    fn hsi_fallback_keeps_the_frequencies() {
+        let hsi = HSE25_84MHZ.hsi_fallback();
+        assert_eq!((hsi.source, hsi.crystal_hz, hsi.crystal_divisor), (ClockSource::Hsi, HSI_HZ, 16));
+        assert_eq!(hsi.frequencies(), HSE25_84MHZ.frequencies());
+        assert_eq!(CONFIG.hsi_fallback().crystal_divisor, 8);
+        // a 1.5 MHz VCO input can't be made from 16 MHz
+        let odd = ClockConfig { crystal_hz: 12 * MHZ, crystal_divisor: 8, pll_multiplier: 224, ..CONFIG };
+        assert!(odd.valid(F401));
+        assert!(!odd.valid_with_fallback(F401));
+        // the HSI runs at 16 MHz only
+        assert!(!ClockConfig { source: ClockSource::Hsi, ..CONFIG }.valid(F401));
+    }
+
+    #[test]
+    fn timers_run_at_twice_a_divided_apb_clock() {
+        let f = ClockConfig { ahb_divisor: 2, apb1_divisor: 4, apb2_divisor: 2, flash_latency: 1, ..CONFIG }.frequencies();
+        assert_eq!((f.hclk, f.pclk1, f.apb1_timer), (42 * MHZ, 10_500_000, 21 * MHZ));
+        assert_eq!((f.pclk2, f.apb2_timer), (21 * MHZ, 42 * MHZ));
+    }
+
+    #[test]
+    fn register_encodings() {
+        assert_eq!([2, 4, 6, 8].iter().map(|&d| pllp_bits(d)).collect::<Vec<_>>(), [0, 1, 2, 3]);
+        assert!(pllp_bits(3) >= 4 && pllp_bits(0) >= 4 && pllp_bits(10) >= 4);
+        let hpre: Vec<u32> = [1, 2, 4, 8, 16, 64, 128, 256, 512].iter().map(|&d| hpre_bits(d)).collect();
+        assert_eq!(hpre, [0, 8, 9, 10, 11, 12, 13, 14, 15]);
+        assert!(hpre_bits(32) >= 16 && hpre_bits(3) >= 16 && hpre_bits(1024) >= 16);
+        let ppre: Vec<u32> = [1, 2, 4, 8, 16].iter().map(|&d| ppre_bits(d)).collect();
+        assert_eq!(ppre, [0, 4, 5, 6, 7]);
+        assert!(ppre_bits(32) >= 8 && ppre_bits(6) >= 8);
+    }
+}
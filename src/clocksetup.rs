@@ -1,74 +1,98 @@
 use stm32ral::{modify_reg, read_reg};
 
-pub struct ClockConfig {
-    pub crystal_hz: f32,
-    pub crystal_divisor: u32,
-    pub pll_multiplier: u32,
-    pub general_divisor: u32,
-    pub pll48_divisor: u32,
-
-    pub ahb_divisor: u32,
-    pub apb1_divisor: u32,
-    pub apb2_divisor: u32,
-
-    pub flash_latency: u32,
-}
+pub use minipov::clocks::{ClockConfig, ClockSource, Frequencies};
+use minipov::clocks::{hpre_bits, pllp_bits, ppre_bits, Limits, HSE8_84MHZ, HSI_HZ, STM32F401};
+
+use crate::util::InitError;
+
+// the chip on the board and its clock config, see minipov::clocks for more presets
+pub const CHIP: Limits = STM32F401;
+//84MHz CPU/AHB/APB2, 42 MHz APB1, 48 MHz SDIO / USB clock from an 8 MHz crystal
+pub const CLOCKS: ClockConfig = HSE8_84MHZ;
+// fail the build on an illegal clock config or one without an HSI fallback at the same frequencies
+const _: [(); 0 - !CLOCKS.valid_with_fallback(&CHIP) as usize] = [];
 
-pub fn clocksetup(rcc: &stm32ral::rcc::Instance, flash: &stm32ral::flash::Instance) {
-    //84MHz CPU/AHB/APB2, 42 MHz APB1, 48 MHz SDIO / USB clock
-    configure_clocks(rcc, flash, &(ClockConfig {
-        crystal_hz: 8000000.0,
-        crystal_divisor: 4,
-        pll_multiplier: 168,
-        general_divisor: stm32ral::rcc::PLLCFGR::PLLP::RW::Div4,
-        pll48_divisor: 7,
-        ahb_divisor: stm32ral::rcc::CFGR::HPRE::RW::Div1,
-        apb1_divisor: stm32ral::rcc::CFGR::PPRE1::RW::Div2,
-        apb2_divisor: stm32ral::rcc::CFGR::PPRE2::RW::Div1,
-        flash_latency: 2, //2 wait states for 84MHz at 3.3V.
-    }));
+// bus and timer clocks, the SPI and timer setup get derived from them
+pub const FREQUENCIES: Frequencies = CLOCKS.frequencies();
+// resulting core clock of clocksetup, the DWT cycle counter runs at this rate
+pub const SYSCLK_HZ: u32 = FREQUENCIES.hclk;
+
+// The crystal gets 100 ms on the 16 MHz HSI to start (it needs 2 ms typically)
+const HSE_STARTUP_CYCLES: u32 = HSI_HZ / 10;
+// HSI, PLL and clock switches take microseconds, 1 ms at 16 MHz is plenty
+const CLOCK_TIMEOUT_CYCLES: u32 = HSI_HZ / 1000;
+
+// Returns the clock source the PLL runs from, the HSI if the crystal didn't start.
+// Needs the DWT cycle counter running for the timeouts.
+pub fn clocksetup(rcc: &stm32ral::rcc::Instance, flash: &stm32ral::flash::Instance) -> Result<ClockSource, InitError> {
+    configure_clocks(rcc, flash, &CLOCKS)
 }
 
 pub fn configure_clocks(
     rcc: &stm32ral::rcc::Instance,
     flash: &stm32ral::flash::Instance,
     cfg: &ClockConfig,
-) {
+) -> Result<ClockSource, InitError> {
     // Switch to the internal 16MHz oscillator while messing with the PLL.
     modify_reg!(stm32ral::rcc, rcc, CR, HSION: On);
-    block_until! { read_reg!(stm32ral::rcc, rcc, CR, HSIRDY == Ready) }
+    block_until_timeout! { CLOCK_TIMEOUT_CYCLES, read_reg!(stm32ral::rcc, rcc, CR, HSIRDY == Ready) }
+        .map_err(|_| InitError::Hsi)?;
 
     // Make the switch.
     modify_reg!(stm32ral::rcc, rcc, CFGR, SW: HSI);
-    block_until! { read_reg!(stm32ral::rcc, rcc, CFGR, SWS == HSI) }
+    block_until_timeout! { CLOCK_TIMEOUT_CYCLES, read_reg!(stm32ral::rcc, rcc, CFGR, SWS == HSI) }
+        .map_err(|_| InitError::ClockSwitch)?;
 
     // Turn off the PLL.
     modify_reg!(stm32ral::rcc, rcc, CR, PLLON: Off);
-    block_while! { read_reg!(stm32ral::rcc, rcc, CR, PLLRDY == Ready) }
+    block_while_timeout! { CLOCK_TIMEOUT_CYCLES, read_reg!(stm32ral::rcc, rcc, CR, PLLRDY == Ready) }
+        .map_err(|_| InitError::Pll)?;
+
+    // Switch on the crystal oscillator, fall back to the HSI if it doesn't start.
+    let fallback = cfg.hsi_fallback();
+    let cfg = if cfg.source == ClockSource::Hse && start_hse(rcc) { cfg } else { &fallback };
 
     // Apply divisors before boosting frequency.
-    modify_reg!(stm32ral::rcc, rcc, CFGR, HPRE: cfg.ahb_divisor, PPRE1: cfg.apb1_divisor, PPRE2: cfg.apb2_divisor);
+    modify_reg!(
+        stm32ral::rcc,
+        rcc,
+        CFGR,
+        HPRE: hpre_bits(cfg.ahb_divisor),
+        PPRE1: ppre_bits(cfg.apb1_divisor),
+        PPRE2: ppre_bits(cfg.apb2_divisor)
+    );
 
     // Configure the flash latency and enable flash cache and prefetching
     modify_reg!(stm32ral::flash, flash, ACR, LATENCY: cfg.flash_latency, DCEN: 1, ICEN: 1, PRFTEN: 1);
 
-    // Switch on the crystal oscillator.
-    modify_reg!(stm32ral::rcc, rcc, CR, HSEON: On);
-    block_until! { read_reg!(stm32ral::rcc, rcc, CR, HSERDY == Ready) }
-
     // Configure the PLL.
     modify_reg!(stm32ral::rcc, rcc, PLLCFGR, 
         PLLM: cfg.crystal_divisor, 
         PLLN: cfg.pll_multiplier, 
         PLLQ: cfg.pll48_divisor, 
-        PLLP: cfg.general_divisor, 
-        PLLSRC: HSE);
+        PLLP: pllp_bits(cfg.general_divisor), 
+        PLLSRC: (cfg.source == ClockSource::Hse) as u32);
 
     // Turn it on.
     modify_reg!(stm32ral::rcc, rcc, CR, PLLON: On);
-    block_until! { read_reg!(stm32ral::rcc, rcc, CR, PLLRDY == Ready) }
+    block_until_timeout! { CLOCK_TIMEOUT_CYCLES, read_reg!(stm32ral::rcc, rcc, CR, PLLRDY == Ready) }
+        .map_err(|_| InitError::Pll)?;
 
     // Select PLL as clock source.
     modify_reg!(stm32ral::rcc, rcc, CFGR, SW: PLL);
-    block_until! { read_reg!(stm32ral::rcc, rcc, CFGR, SWS == PLL) }
+    block_until_timeout! { CLOCK_TIMEOUT_CYCLES, read_reg!(stm32ral::rcc, rcc, CFGR, SWS == PLL) }
+        .map_err(|_| InitError::ClockSwitch)?;
+
+    Ok(cfg.source)
+}
+
+// Switch on the crystal oscillator and wait a bounded time for it,
+// a missing or broken crystal gets switched off again.
+fn start_hse(rcc: &stm32ral::rcc::Instance) -> bool {
+    modify_reg!(stm32ral::rcc, rcc, CR, HSEON: On);
+    if block_until_timeout! { HSE_STARTUP_CYCLES, read_reg!(stm32ral::rcc, rcc, CR, HSERDY == Ready) }.is_ok() {
+        return true;
+    }
+    modify_reg!(stm32ral::rcc, rcc, CR, HSEON: Off);
+    false
 }
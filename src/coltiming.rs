@@ -0,0 +1,71 @@
+// Column timing that follows the fan speed.
+// A revolution is divided into slots_per_rev column slots, the image window uses
+// some of them, the rest gives the timers time to re-arm before the next tacho edge.
+// The column slot length is a whole number of TIM2 counts.
+
+pub struct ColumnTiming {
+    // timer clock cycles per TIM2 count
+    pub count_cycles: u32,
+    // column slots per revolution
+    pub slots_per_rev: u32,
+    // shortest column: the column data plus the latch time has to fit
+    pub min_counts: u32,
+    // longest column: the image window has to fit into the 16 bit TIM3 counter (counts TIM2 steps)
+    pub max_counts: u32,
+}
+
+impl ColumnTiming {
+    // TIM2 counts per column for a revolution period in timer clock cycles
+    pub fn counts_per_column(&self, rev_period: u32) -> u32 {
+        let slot_cycles = self.slots_per_rev as u64 * self.count_cycles as u64;
+        let counts = (rev_period as u64 + slot_cycles / 2) / slot_cycles;
+        if counts < self.min_counts as u64 {
+            self.min_counts
+        } else if counts > self.max_counts as u64 {
+            self.max_counts
+        } else {
+            counts as u32
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timing::DisplayTiming;
+
+    // one driver at 84 MHz like timersetup::TIMING
+    const TIMING: ColumnTiming = DisplayTiming {
+        columns: 128,
+        blank_columns: 1,
+        words_per_column: 14,
+        spi_bit_cycles: 16,
+        column_counts: 16,
+    }
+    .column_timing();
+
+    #[test]
+    fn columns_span_the_revolution() {
+        // 50 revolutions per second at 84 MHz
+        let counts = TIMING.counts_per_column(84_000_000 / 50);
+        assert_eq!(counts, 25);
+        let span = counts * TIMING.count_cycles * TIMING.slots_per_rev;
+        assert!((span as i64 - 84_000_000 / 50).abs() < (TIMING.count_cycles * TIMING.slots_per_rev) as i64 / 2);
+    }
+
+    #[test]
+    fn follows_the_fan_speed() {
+        let fast = TIMING.counts_per_column(84_000_000 / 60);
+        let slow = TIMING.counts_per_column(84_000_000 / 40);
+        assert!(fast < 25 && slow > 25);
+    }
+
+    #[test]
+    fn clamps_to_the_timer_limits() {
+        assert_eq!((TIMING.min_counts, TIMING.max_counts), (9, 504));
+        assert_eq!(TIMING.counts_per_column(84_000_000 / 500), 9);
+        assert_eq!(TIMING.counts_per_column(84_000_000), 504);
+        assert_eq!(TIMING.counts_per_column(0), 9);
+        assert_eq!(TIMING.counts_per_column(u32::MAX), 504);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::canvas::Canvas;
+use crate::gamma::CURVE;
+use crate::tlc59711::Tlc59711Command;
+
+// columns around the cylinder, set with MINIPOV_COLS=<n> at build time (128)
+pub const COLS: usize = include!(concat!(env!("OUT_DIR"), "/cols.rs"));
+// number of daisy chained TLC59711, set with MINIPOV_DRIVERS=<n> at build time
+pub const DRIVERS: usize = include!(concat!(env!("OUT_DIR"), "/drivers.rs"));
+pub const ROWS_PER_DRIVER: usize = 12;
+pub const ROWS: usize = DRIVERS * ROWS_PER_DRIVER;
+// 2 command words plus one 16 bit PWM word per output
+pub const U16PERDRIVER: usize = 2 + ROWS_PER_DRIVER;
+// u16 words sent per column (for all drivers)
+pub const U16PERROW: usize = DRIVERS * U16PERDRIVER;
+pub const BUFLEN: usize = COLS * U16PERROW;
+
+// the DMA transfers the whole buffer at once, NDTR is 16 bits wide
+const _: [(); 0 - !(BUFLEN <= 0xFFFF) as usize] = [];
+
+#[repr(align(128))]
+pub struct DMAbuffer (
+    pub [u16; BUFLEN]
+);
+
+impl DMAbuffer {
+    // command sent in front of every column
+    pub const COMMAND: Tlc59711Command = Tlc59711Command::DEFAULT;
+
+    pub const fn new() -> Self {
+        DMAbuffer([0; BUFLEN])
+    }
+
+    // Index of the first word (command) of a driver within the buffer.
+    // The data shifts through the chain, so the driver next to the mcu (driver 0, rows 0..12)
+    // gets its data last.
+    fn driver_index(col: usize, driver: usize) -> usize {
+        col * U16PERROW + (DRIVERS - 1 - driver) * U16PERDRIVER
+    }
+
+    // write the two command words of every driver in front of a column
+    pub fn set_command(&mut self, col: usize, cmd: &Tlc59711Command) {
+        let words = cmd.words();
+        for driver in 0..DRIVERS {
+            let i = DMAbuffer::driver_index(col, driver);
+            self.0[i] = words[0];
+            self.0[i + 1] = words[1];
+        }
+    }
+    // write the command words of all columns, pixel data stays untouched
+    pub fn set_commands(&mut self, cmd: &Tlc59711Command) {
+        for col in 0..COLS {
+            self.set_command(col, cmd);
+        }
+    }
+    pub fn clear_col(&mut self, col: usize) {
+        self.fill_col(col, 0);
+    }
+    fn pixel_index(col: usize, row: usize) -> usize {
+        DMAbuffer::driver_index(col, row / ROWS_PER_DRIVER) + 2 + row % ROWS_PER_DRIVER
+    }
+    // val is an 8 bit brightness, mapped through the gamma curve
+    pub fn setpixel(&mut self, col: usize, row: usize, val: u8) {
+        self.0[DMAbuffer::pixel_index(col, row)] = CURVE[val as usize];
+    }
+    // the 16 bit PWM value of a pixel
+    pub fn pwm(&self, col: usize, row: usize) -> u16 {
+        self.0[DMAbuffer::pixel_index(col, row)]
+    }
+    // Write a raw PWM value to one output of a driver.
+    // channel is the position in the grayscale data, 0 is OUTB3 ... 11 is OUTR0
+    pub fn set_pwm(&mut self, col: usize, driver: usize, channel: usize, pwm: u16) {
+        self.0[DMAbuffer::driver_index(col, driver) + 2 + channel] = pwm;
+    }
+    pub fn set_col(&mut self, col: usize) {
+        self.fill_col(col, 255);
+    }
+    fn fill_col(&mut self, col: usize, val: u8) {
+        self.set_command(col, &DMAbuffer::COMMAND);
+        for row in 0..ROWS {
+            self.setpixel(col, row, val);
+        }
+    }
+}
+
+// monochrome mode, every driver output is a row
+impl Canvas for DMAbuffer {
+    type Color = u8;
+    const OFF: u8 = 0;
+
+    fn rows(&self) -> usize {
+        ROWS
+    }
+    fn put_pixel(&mut self, col: usize, row: usize, color: u8) {
+        self.setpixel(col, row, color);
+    }
+    fn clear_col(&mut self, col: usize) {
+        DMAbuffer::clear_col(self, col);
+    }
+}
+
+impl Default for DMAbuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffer_is_128_byte_aligned() {
+        assert_eq!(core::mem::align_of::<DMAbuffer>(), 128);
+        // the words padded up to the alignment, the DMA only transfers BUFLEN of them
+        assert_eq!(core::mem::size_of::<DMAbuffer>(), (BUFLEN * 2 + 127) & !127);
+    }
+
+    #[test]
+    fn set_and_clear_col_write_command_header() {
+        let mut buf = DMAbuffer::new();
+        buf.set_col(5);
+        for block in buf.0[5 * U16PERROW..6 * U16PERROW].chunks(U16PERDRIVER) {
+            assert_eq!(block[..2], DMAbuffer::COMMAND.words());
+            assert!(block[2..].iter().all(|&v| v == 65535));
+        }
+        buf.clear_col(5);
+        for block in buf.0[5 * U16PERROW..6 * U16PERROW].chunks(U16PERDRIVER) {
+            assert_eq!(block[..2], DMAbuffer::COMMAND.words());
+            assert!(block[2..].iter().all(|&v| v == 0));
+        }
+        // neighbours untouched
+        assert_eq!(buf.0[4 * U16PERROW], 0);
+        assert_eq!(buf.0[6 * U16PERROW], 0);
+    }
+
+    #[test]
+    fn set_commands_keeps_pixels() {
+        let mut buf = DMAbuffer::new();
+        for col in 0..COLS {
+            buf.set_col(col);
+        }
+        let mut cmd = DMAbuffer::COMMAND;
+        cmd.set_brightness_all(0x10);
+        buf.set_commands(&cmd);
+        for block in buf.0.chunks(U16PERDRIVER) {
+            assert_eq!(Tlc59711Command::from_words([block[0], block[1]]), Some(cmd));
+            assert!(block[2..].iter().all(|&v| v == 65535));
+        }
+    }
+
+    #[test]
+    fn setpixel_only_touches_its_pwm_word() {
+        let mut buf = DMAbuffer::new();
+        buf.clear_col(COLS - 1);
+        buf.setpixel(COLS - 1, ROWS_PER_DRIVER - 1, 128);
+        assert_eq!(buf.0[BUFLEN - 1], CURVE[128]);
+        assert!(buf.0[BUFLEN - U16PERDRIVER + 2..BUFLEN - 1].iter().all(|&v| v == 0));
+    }
+
+    #[test]
+    fn last_driver_in_the_chain_is_sent_first() {
+        let mut buf = DMAbuffer::new();
+        buf.setpixel(1, ROWS - 1, 255);
+        assert_eq!(buf.0[U16PERROW + 2 + ROWS_PER_DRIVER - 1], 0xFFFF);
+        assert_eq!(buf.0.iter().filter(|&&v| v != 0).count(), 1);
+        // row 0 sits on the driver next to the mcu which gets the last block
+        buf.setpixel(1, 0, 255);
+        assert_eq!(buf.0[2 * U16PERROW - U16PERDRIVER + 2], 0xFFFF);
+    }
+}
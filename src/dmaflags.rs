@@ -0,0 +1,116 @@
+// Interrupt flags of the DMA streams. Each stream has a group of 6 bits in the status
+// registers (LISR for streams 0-3, HISR for 4-7), the clear registers LIFCR/HIFCR use the same layout.
+
+// FIFO error
+pub const FEIF: u32 = 1 << 0;
+// direct mode error
+pub const DMEIF: u32 = 1 << 2;
+// transfer error
+pub const TEIF: u32 = 1 << 3;
+// half transfer
+pub const HTIF: u32 = 1 << 4;
+// transfer complete
+pub const TCIF: u32 = 1 << 5;
+pub const ALL: u32 = FEIF | DMEIF | TEIF | HTIF | TCIF;
+
+pub const STREAMS: usize = 8;
+
+// stream in the high registers
+pub const fn high(stream: usize) -> bool {
+    stream >= 4
+}
+
+// position of the flag group of a stream in its register
+pub const fn shift(stream: usize) -> u32 {
+    let n = (stream % 4) as u32;
+    (n & 1) * 6 + (n >> 1) * 16
+}
+
+// What an interrupt of a stream means for the buffers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    // the stream finished a buffer and switched to the other one
+    Complete,
+    // transfer or FIFO error, the stream has to stop
+    Error,
+    // nothing got transferred: no flag of interest, or the stream is stopped
+    // and waits for its restart (stopping it sets TCIF)
+    Ignore,
+}
+
+// What the interrupts of a stream reported so far
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Counters {
+    pub complete: u32,
+    pub transfer_errors: u32,
+    pub fifo_errors: u32,
+}
+
+impl Counters {
+    pub const fn new() -> Self {
+        Counters {
+            complete: 0,
+            transfer_errors: 0,
+            fifo_errors: 0,
+        }
+    }
+
+    // Count the flags of one interrupt, true if it reported an error.
+    // A transfer complete that comes with an error doesn't count, the buffer got garbled.
+    pub fn count(&mut self, flags: u32) -> bool {
+        let error = flags & (TEIF | FEIF) != 0;
+        if flags & TEIF != 0 {
+            self.transfer_errors = self.transfer_errors.wrapping_add(1);
+        }
+        if flags & FEIF != 0 {
+            self.fifo_errors = self.fifo_errors.wrapping_add(1);
+        }
+        if flags & TCIF != 0 && !error {
+            self.complete = self.complete.wrapping_add(1);
+        }
+        error
+    }
+
+    pub fn errors(&self) -> u32 {
+        self.transfer_errors.wrapping_add(self.fifo_errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flag_groups() {
+        let shifts: Vec<u32> = (0..STREAMS).map(shift).collect();
+        assert_eq!(shifts, [0, 6, 16, 22, 0, 6, 16, 22]);
+        assert!(!high(3) && high(4));
+        // stream 6: TCIF6 is bit 21 and FEIF6 bit 16 of HISR
+        assert_eq!(TCIF << shift(6), 1 << 21);
+        assert_eq!(FEIF << shift(6), 1 << 16);
+        // the groups don't overlap
+        assert_eq!((ALL << shift(0)) & (ALL << shift(1)), 0);
+        assert_eq!((ALL << shift(1)) & (ALL << shift(2)), 0);
+    }
+
+    #[test]
+    fn counts_each_kind() {
+        let mut counters = Counters::new();
+        assert!(!counters.count(TCIF));
+        assert!(!counters.count(TCIF | HTIF));
+        assert!(counters.count(TEIF));
+        assert!(counters.count(FEIF | TCIF));
+        assert!(counters.count(TEIF | FEIF));
+        // a spurious interrupt
+        assert!(!counters.count(0));
+        assert_eq!(
+            counters,
+            Counters {
+                complete: 2,
+                transfer_errors: 2,
+                fifo_errors: 2,
+            }
+        );
+        assert_eq!(counters.errors(), 4);
+    }
+}
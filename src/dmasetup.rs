@@ -1,76 +1,296 @@
-use stm32ral::{modify_reg, read_reg, write_reg};
+use core::marker::PhantomData;
+
+use cortex_m::peripheral::NVIC;
+use stm32ral::stm32f4::stm32f401::Interrupt;
+use stm32ral::{dma, modify_reg, read_reg, write_reg, RWRegister};
+
+use minipov::dmaflags;
+
+use crate::clocksetup::SYSCLK_HZ;
+use crate::util::{InitError, Timeout};
+
+// a stream stops after the current transfer, that takes microseconds
+const DMA_TIMEOUT_CYCLES: u32 = SYSCLK_HZ / 1000;
+
+// The stream and channel of a DoubleBufferStream are types, so there is no stream 8 or channel 9
+// and the flag positions of the stream are constants.
+pub trait Stream {
+    const INDEX: usize;
+    // the flags are in the high or low status register at SHIFT
+    const HIGH: bool = dmaflags::high(Self::INDEX);
+    const SHIFT: u32 = dmaflags::shift(Self::INDEX);
+    fn registers(dma: &dma::RegisterBlock) -> StreamRegisters<'_>;
+}
+
+pub trait Channel {
+    const INDEX: u32;
+}
+
+macro_rules! streams {
+    ($($name:ident = $index:expr => $cr:ident $ndtr:ident $par:ident $m0ar:ident $m1ar:ident $fcr:ident),*) => {
+        $(
+            pub struct $name;
+            impl Stream for $name {
+                const INDEX: usize = $index;
+                fn registers(dma: &dma::RegisterBlock) -> StreamRegisters<'_> {
+                    StreamRegisters {
+                        CR0: &dma.$cr,
+                        NDTR0: &dma.$ndtr,
+                        PAR0: &dma.$par,
+                        M0AR0: &dma.$m0ar,
+                        M1AR0: &dma.$m1ar,
+                        FCR0: &dma.$fcr,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! channels {
+    ($($name:ident = $index:expr),*) => {
+        $(
+            pub struct $name;
+            impl Channel for $name {
+                const INDEX: u32 = $index;
+            }
+        )*
+    };
+}
+
+#[allow(dead_code)]
+mod markers {
+    use super::{dma, Channel, Stream, StreamRegisters};
+    streams!(
+        Stream0 = 0 => CR0 NDTR0 PAR0 M0AR0 M1AR0 FCR0,
+        Stream1 = 1 => CR1 NDTR1 PAR1 M0AR1 M1AR1 FCR1,
+        Stream2 = 2 => CR2 NDTR2 PAR2 M0AR2 M1AR2 FCR2,
+        Stream3 = 3 => CR3 NDTR3 PAR3 M0AR3 M1AR3 FCR3,
+        Stream4 = 4 => CR4 NDTR4 PAR4 M0AR4 M1AR4 FCR4,
+        Stream5 = 5 => CR5 NDTR5 PAR5 M0AR5 M1AR5 FCR5,
+        Stream6 = 6 => CR6 NDTR6 PAR6 M0AR6 M1AR6 FCR6,
+        Stream7 = 7 => CR7 NDTR7 PAR7 M0AR7 M1AR7 FCR7
+    );
+    channels!(Channel0 = 0, Channel1 = 1, Channel2 = 2, Channel3 = 3, Channel4 = 4, Channel5 = 5, Channel6 = 6, Channel7 = 7);
+}
+pub use markers::*;
+
+// DMA1 stream 6 channel 2 is TIM4_UP, it feeds SPI2 on every TIM4 update
+pub type SpiStream = DoubleBufferStream<Stream6, Channel2>;
+
+// CR, NDTR, PAR, M0AR, M1AR and FCR of one stream, borrowed from the register block.
+// Named like the ones of stream 0 so the stm32ral field definitions work for every stream.
+#[allow(non_snake_case)]
+pub struct StreamRegisters<'a> {
+    CR0: &'a RWRegister<u32>,
+    NDTR0: &'a RWRegister<u32>,
+    PAR0: &'a RWRegister<u32>,
+    M0AR0: &'a RWRegister<u32>,
+    M1AR0: &'a RWRegister<u32>,
+    FCR0: &'a RWRegister<u32>,
+}
+
+// A DMA stream in double buffer mode feeding a peripheral from two memory buffers.
+// While the DMA reads the current target the idle target can be swapped for the next buffer.
+pub struct DoubleBufferStream<S: Stream, C: Channel> {
+    dma: dma::Instance,
+    // the interrupt of the stream
+    interrupt: Interrupt,
+    // words per buffer and the buffers the stream owns as memory 0 and 1,
+    // kept to restart the stream after an error
+    len: u32,
+    targets: [u32; 2],
+    // the memory that became active at the last transfer complete handled,
+    // the buffers got handed over for that one, so a restart starts over there
+    active: usize,
+    restart_pending: bool,
+    // restarts after an error so far
+    restarts: u32,
+    _stream: PhantomData<(S, C)>,
+}
+
+impl<S: Stream, C: Channel> DoubleBufferStream<S, C> {
+    // The stream owns the DMA controller, no other stream of it is in use
+    pub fn new(dma: dma::Instance, interrupt: Interrupt) -> Self {
+        DoubleBufferStream {
+            dma,
+            interrupt,
+            len: 0,
+            targets: [0; 2],
+            active: 0,
+            restart_pending: false,
+            restarts: 0,
+            _stream: PhantomData,
+        }
+    }
+
+    fn regs(&self) -> StreamRegisters<'_> {
+        S::registers(&self.dma)
+    }
+
+    // Set up the stream to send len u16 words from the buffers bufa and bufb to the register at par
+    // on every request of the peripheral, circular in double buffer mode and enable it.
+    pub fn configure(&mut self, par: u32, bufa: u32, bufb: u32, len: u32) -> Result<(), InitError> {
+        self.len = len;
+        self.targets = [bufa, bufb];
+        self.active = 0;
+        let regs = &self.regs();
+        self.disable().map_err(|_| InitError::DmaEnable)?;
+        //clear all interrupt Flags of the stream
+        self.clear_flags(dmaflags::ALL);
+        //set the peripheral data register as DMA destination
+        write_reg!(dma, regs, PAR0, par);
+        //set the Buffer A as DMA Doublebuffer 0 source
+        write_reg!(dma, regs, M0AR0, bufa);
+        //set the Buffer B as DMA Doublebuffer 1 source
+        write_reg!(dma, regs, M1AR0, bufb);
+        //set the Number of words to transfer
+        write_reg!(dma, regs, NDTR0, len);
+        //select the DMA channel, DMA Flow controller, Prio=high
+        //Circular mode, double buffered, Mem to Peripheral,
+        // Memory is half word incremented periferal is fixed half word
+        //All interrupts exept Half complete and Direct Mode Error are enabled
+        // Transfer error: Bus error during DMA read/write or
+        // modification of Memory adress register of active doublebuffer
+        // FFIO Error: Over/Underrun or incompatible MBurst setting for FIFO threshold
+        modify_reg!(
+            dma,
+            regs,
+            CR0,
+            CHSEL: C::INDEX,
+            PFCTRL: DMA,
+            PL: High,
+            CIRC: Enabled,
+            DBM: Enabled,
+            DIR: MemoryToPeripheral,
+            MBURST: Single,
+            MINC: Incremented,
+            MSIZE: Bits16,
+            PBURST: Single,
+            PINC: Fixed,
+            PINCOS: PSIZE,
+            PSIZE: Bits16,
+            DMEIE: Disabled,
+            HTIE: Disabled,
+            TCIE: Enabled,
+            TEIE: Enabled
+        );
+        //Fifo direct mode, fifo error interrupt enabled, FIFO Threshold Half full
+        write_reg!(dma, regs, FCR0, DMDIS: Disabled, FEIE: Enabled, FTH: Half);
+        modify_reg!(dma, regs, CR0, EN: Enabled);
+        block_until_timeout! { DMA_TIMEOUT_CYCLES, read_reg!(dma, regs, CR0, EN == Enabled) }
+            .map_err(|_| InitError::DmaEnable)
+    }
+
+    // Stop the stream for good, it stops after the current transfer
+    pub fn stop(&mut self) -> Result<(), Timeout> {
+        self.restart_pending = false;
+        self.disable()
+    }
+
+    // Stop the stream after an error, restart it at the next image boundary.
+    // Clearing EN sets TCIF, drop it and the interrupt it left pending, nothing got transferred.
+    pub fn abort(&mut self) -> Result<(), Timeout> {
+        self.restart_pending = true;
+        let stopped = self.disable();
+        self.clear_flags(dmaflags::ALL);
+        NVIC::unpend(self.interrupt);
+        stopped
+    }
+
+    pub fn restart_pending(&self) -> bool {
+        self.restart_pending
+    }
+
+    // Start over with the whole buffer at a point where the peripheral sends no requests.
+    // The stream picks up with the buffer that was active at the last transfer complete handled
+    // and the idle target scheduled after it, just like the triple buffer sees them.
+    // The targets are the ones last handed to the stream, not the address registers,
+    // a transfer error might have come from writing them.
+    pub fn restart(&mut self) -> Result<(), Timeout> {
+        let regs = &self.regs();
+        self.disable()?;
+        self.clear_flags(dmaflags::ALL);
+        write_reg!(dma, regs, M0AR0, self.targets[0]);
+        write_reg!(dma, regs, M1AR0, self.targets[1]);
+        write_reg!(dma, regs, NDTR0, self.len);
+        modify_reg!(dma, regs, CR0, CT: self.active as u32, EN: Enabled);
+        self.restart_pending = false;
+        self.restarts = self.restarts.wrapping_add(1);
+        Ok(())
+    }
+
+    pub fn restarts(&self) -> u32 {
+        self.restarts
+    }
+
+    fn disable(&self) -> Result<(), Timeout> {
+        let regs = &self.regs();
+        modify_reg!(dma, regs, CR0, EN: Disabled);
+        block_until_timeout! { DMA_TIMEOUT_CYCLES, read_reg!(dma, regs, CR0, EN == Disabled) }
+    }
+
+    // memory 0 or 1, the one the DMA reads from right now
+    pub fn active_memory(&self) -> usize {
+        read_reg!(dma, &self.regs(), CR0, CT) as usize
+    }
+
+    // A transfer completed and the DMA switched memories: the finished target and the now active one.
+    // Remembers the active memory for a restart.
+    pub fn switched(&mut self) -> (u32, u32) {
+        self.active = self.active_memory();
+        (self.targets[1 - self.active], self.targets[self.active])
+    }
+
+    // Replace the buffer the DMA switches to next, the active one may be scheduled again.
+    // Writing the address register of the active memory is a transfer error,
+    // so it has to be done before the current transfer completes.
+    pub fn set_idle_target(&mut self, buf: u32) {
+        if self.active_memory() == 0 {
+            write_reg!(dma, &self.regs(), M1AR0, buf);
+            self.targets[1] = buf;
+        } else {
+            write_reg!(dma, &self.regs(), M0AR0, buf);
+            self.targets[0] = buf;
+        }
+    }
+
+    // Interrupt flags of the stream, see dmaflags for the bits
+    pub fn flags(&self) -> u32 {
+        let isr = if S::HIGH {
+            read_reg!(dma, self.dma, HISR)
+        } else {
+            read_reg!(dma, self.dma, LISR)
+        };
+        (isr >> S::SHIFT) & dmaflags::ALL
+    }
+
+    // Clear the given interrupt flags of the stream
+    pub fn clear_flags(&self, flags: u32) {
+        let flags = (flags & dmaflags::ALL) << S::SHIFT;
+        if S::HIGH {
+            write_reg!(dma, self.dma, HIFCR, flags);
+        } else {
+            write_reg!(dma, self.dma, LIFCR, flags);
+        }
+    }
+}
 
 pub fn dmaconfig(
     rcc: &stm32ral::rcc::Instance,
-    dma: &stm32ral::dma::Instance,
+    stream: &mut SpiStream,
     spi: &stm32ral::spi::Instance,
     bufa: u32,
     bufb: u32
-) {
-    //const PRESCALER: u32 = 0x3FFF;
+) -> Result<(), InitError> {
     //Enable DMA1 clocks
     modify_reg!(stm32ral::rcc, rcc, AHB1ENR, DMA1EN: Enabled);
     cortex_m::asm::dmb(); // ensure DMA is powered on before we write to it
-    write_reg!(stm32ral::dma, dma, CR6, EN: Disabled); //Disable stream 6
-
-    //clear all interrupt Flags for stream_6
-    block_until! { read_reg!(stm32ral::dma, dma, CR6, EN == Disabled) }
-    write_reg!(
-        stm32ral::dma,
-        dma,
-        HIFCR,
-        CDMEIF6: Clear,
-        CFEIF6: Clear,
-        CHTIF6: Clear,
-        CTCIF6: Clear,
-        CTEIF6: Clear
-    );
-    //set the USART Data register as DMA destination
-    write_reg!(stm32ral::dma, dma, PAR6, &spi.DR as *const _ as u32);
-    //set the Buffer A as DMA Doublebuffer 0 source
-    write_reg!(stm32ral::dma, dma, M0AR6, bufa);
-    //set the Buffer B as DMA Doublebuffer 1 source
-    write_reg!(stm32ral::dma, dma, M1AR6, bufb);
-    //set the Number of bytes to transfer
-    write_reg!(stm32ral::dma, dma, NDTR6, super::BUFLEN as u32);
-    //select the DMA channel 2 for stream 6, DMA Flow controller, Prio=high
-    //Circular mode, double buffered, Mem to Peripheral,
-    // Memory is byte incremented periferal is fixed byte
-    //All interrupts exept Half complete and Direct Mode Error are enabled
-    // Transfer error: Bus error during DMA read/write or
-    // modification of Memory adress register of active doublebuffer
-    // FFIO Error: Over/Underrun or incompatible MBurst setting for FIFO threshold
-    modify_reg!(
-        stm32ral::dma,
-        dma,
-        CR6,
-        CHSEL: 2,
-        PFCTRL: DMA,
-        PL: High,
-        CIRC: Enabled,
-        DBM: Enabled,
-        DIR: MemoryToPeripheral,
-        MBURST: Single,
-        MINC: Incremented,
-        MSIZE: Bits16,
-        PBURST: Single,
-        PINC: Fixed,
-        PINCOS: PSIZE,
-        PSIZE: Bits16,
-        DMEIE: Disabled,
-        HTIE: Disabled,
-        TCIE: Enabled,
-        TEIE: Enabled
-    );
-    //Fifo direct mode, fifo error interrupt enabled, FIFO Threshold Half full
-    write_reg!(
-        stm32ral::dma,
-        dma,
-        FCR6,
-        DMDIS: Disabled,
-        FEIE: Enabled,
-        FTH: Half
-    );
-    modify_reg!(stm32ral::dma, dma, CR6, EN: Enabled); //Enable stream 6
-    block_until! { read_reg!(stm32ral::dma, dma, CR6, EN == Enabled) };
+    // TIM4 requests a U16 at a time for the SPI Data register
+    stream.configure(&spi.DR as *const _ as u32, bufa, bufb, minipov::BUFLEN as u32)
+}
+
+// Stop the stream for good, TIM4 requests get ignored from now on
+pub fn dmastop(stream: &mut SpiStream) -> Result<(), Timeout> {
+    stream.stop()
 }
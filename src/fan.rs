@@ -0,0 +1,212 @@
+// Fan control with soft start, run timeout and a safe shutdown.
+// The pcb rotates with the fan, so it must not spin forever: after run_ms it stops on its own.
+// Before the fan gets cut the leds are blanked, a standing fan would leave the last column lit.
+// Timestamps come from a wrapping tick counter, update has to be called more often than it wraps.
+
+// PWM duty of a fan at full speed
+pub const DUTY_FULL: u32 = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FanState {
+    Off,
+    // ramping the PWM duty up
+    Starting,
+    Running,
+    // leds blanked, waiting for the blank command to reach the drivers before cutting the fan
+    Stopping,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FanConfig {
+    // duty the ramp starts with, below that the fan won't turn at all
+    pub start_duty: u32,
+    // soft start ramp from start_duty to full speed
+    pub ramp_ms: u32,
+    // switch off after running that long, 0 runs until stop gets called
+    pub run_ms: u32,
+    // time to get the blank command out to the drivers
+    pub blank_ms: u32,
+}
+
+pub struct Fan {
+    tick_hz: u32,
+    config: FanConfig,
+    state: FanState,
+    last_tick: u32,
+    // ticks since entering the current state and since the start
+    in_state: u64,
+    running: u64,
+}
+
+impl Fan {
+    pub const fn new(tick_hz: u32, config: FanConfig) -> Self {
+        Fan {
+            tick_hz,
+            config,
+            state: FanState::Off,
+            last_tick: 0,
+            in_state: 0,
+            running: 0,
+        }
+    }
+
+    // start the fan with a soft start ramp, a running fan restarts its run timer
+    pub fn start(&mut self, now: u32) {
+        match self.state {
+            FanState::Off | FanState::Stopping => self.enter(FanState::Starting, now),
+            FanState::Starting | FanState::Running => {}
+        }
+        self.running = 0;
+    }
+
+    // blank the leds and cut the fan once they are dark
+    pub fn stop(&mut self, now: u32) {
+        match self.state {
+            FanState::Starting | FanState::Running => self.enter(FanState::Stopping, now),
+            FanState::Off | FanState::Stopping => {}
+        }
+    }
+
+    // emergency stop, no time for blanking through the image
+    pub fn cut(&mut self, now: u32) {
+        self.enter(FanState::Off, now);
+    }
+
+    // advance the state machine to the timestamp now
+    pub fn update(&mut self, now: u32) -> FanState {
+        let elapsed = now.wrapping_sub(self.last_tick) as u64;
+        self.last_tick = now;
+        self.in_state += elapsed;
+        self.running += elapsed;
+        match self.state {
+            FanState::Starting | FanState::Running => {
+                if self.config.run_ms > 0 && self.running >= self.ticks(self.config.run_ms) {
+                    self.enter(FanState::Stopping, now);
+                } else if self.state == FanState::Starting && self.in_state >= self.ticks(self.config.ramp_ms) {
+                    self.enter(FanState::Running, now);
+                }
+            }
+            FanState::Stopping => {
+                if self.in_state >= self.ticks(self.config.blank_ms) {
+                    self.enter(FanState::Off, now);
+                }
+            }
+            FanState::Off => {}
+        }
+        self.state
+    }
+
+    pub fn state(&self) -> FanState {
+        self.state
+    }
+
+    // PWM duty out of DUTY_FULL
+    pub fn duty(&self) -> u32 {
+        match self.state {
+            FanState::Off => 0,
+            FanState::Starting => {
+                let ramp = self.ticks(self.config.ramp_ms).max(1);
+                let start = self.config.start_duty.min(DUTY_FULL) as u64;
+                let duty = start + (DUTY_FULL as u64 - start) * self.in_state.min(ramp) / ramp;
+                duty as u32
+            }
+            // keeps turning while the leds get blanked
+            FanState::Running | FanState::Stopping => DUTY_FULL,
+        }
+    }
+
+    // the leds must stay dark unless the fan runs at full speed
+    pub fn blank(&self) -> bool {
+        self.state != FanState::Running
+    }
+
+    fn enter(&mut self, state: FanState, now: u32) {
+        self.state = state;
+        self.last_tick = now;
+        self.in_state = 0;
+    }
+
+    fn ticks(&self, ms: u32) -> u64 {
+        ms as u64 * self.tick_hz as u64 / 1000
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HZ: u32 = 1000;
+    const CONFIG: FanConfig = FanConfig {
+        start_duty: 300,
+        ramp_ms: 2000,
+        run_ms: 60_000,
+        blank_ms: 200,
+    };
+
+    #[test]
+    fn off_until_started() {
+        let mut fan = Fan::new(HZ, CONFIG);
+        assert_eq!(fan.update(5000), FanState::Off);
+        assert_eq!(fan.duty(), 0);
+        assert!(fan.blank());
+    }
+
+    #[test]
+    fn soft_start_ramps_up() {
+        let mut fan = Fan::new(HZ, CONFIG);
+        fan.start(100);
+        assert_eq!(fan.duty(), 300);
+        fan.update(1100);
+        assert_eq!(fan.duty(), 650);
+        assert!(fan.blank());
+        assert_eq!(fan.update(2100), FanState::Running);
+        assert_eq!(fan.duty(), DUTY_FULL);
+        assert!(!fan.blank());
+    }
+
+    #[test]
+    fn run_timeout_blanks_before_cutting_the_fan() {
+        let mut fan = Fan::new(HZ, CONFIG);
+        fan.start(0);
+        let mut t = 0;
+        while t < 59_900 {
+            t += 100;
+            assert_ne!(fan.update(t), FanState::Stopping);
+        }
+        assert_eq!(fan.update(60_000), FanState::Stopping);
+        // still turning, but dark
+        assert!(fan.blank());
+        assert_eq!(fan.duty(), DUTY_FULL);
+        assert_eq!(fan.update(60_100), FanState::Stopping);
+        assert_eq!(fan.update(60_200), FanState::Off);
+        assert_eq!(fan.duty(), 0);
+    }
+
+    #[test]
+    fn timeout_longer_than_the_tick_counter_wraps() {
+        // 84 MHz wraps after 51 s
+        let hz = 84_000_000;
+        let mut fan = Fan::new(hz, CONFIG);
+        fan.start(0);
+        let mut t: u32 = 0;
+        for _ in 0..59 {
+            t = t.wrapping_add(hz);
+            assert_ne!(fan.update(t), FanState::Stopping);
+        }
+        assert_eq!(fan.update(t.wrapping_add(hz)), FanState::Stopping);
+    }
+
+    #[test]
+    fn stop_and_restart() {
+        let mut fan = Fan::new(HZ, FanConfig { run_ms: 0, ..CONFIG });
+        fan.start(0);
+        assert_eq!(fan.update(1_000_000), FanState::Running);
+        fan.stop(1_000_000);
+        assert!(fan.blank());
+        fan.start(1_000_100);
+        assert_eq!(fan.state(), FanState::Starting);
+        assert_eq!(fan.duty(), 300);
+        fan.cut(1_000_200);
+        assert_eq!(fan.duty(), 0);
+    }
+}
@@ -0,0 +1,38 @@
+use stm32ral::{modify_reg, write_reg};
+
+use minipov::fan::DUTY_FULL;
+
+use crate::clocksetup::FREQUENCIES;
+
+// TIM1 (APB2 timer clock) counts at 1 MHz, DUTY_FULL counts give a 1 kHz PWM
+const TIM1DIV: u32 = FREQUENCIES.apb2_timer / 1_000_000;
+const _: [(); 0 - (FREQUENCIES.apb2_timer % 1_000_000 != 0) as usize] = [];
+
+pub fn fanconfig(rcc: &stm32ral::rcc::Instance, tim1: &stm32ral::tim1::Instance, gpio: &stm32ral::gpio::Instance) {
+    //Enable Timer clock
+    modify_reg!(stm32ral::rcc, rcc, APB2ENR, TIM1EN: Enabled);
+    modify_reg!(stm32ral::tim1, tim1, PSC, PSC: TIM1DIV - 1);
+    modify_reg!(stm32ral::tim1, tim1, ARR, ARR: DUTY_FULL - 1);
+    //start with the fan off
+    modify_reg!(stm32ral::tim1, tim1, CCR2, CCR: 0);
+    //PWM mode 1 with preload for OC2, OC2REF is high for duty counts
+    modify_reg!(stm32ral::tim1, tim1, CCMR1, OC2M: 0b110, OC2PE: 1);
+    //Only the complementary output CH2N on PB0, active low: '0' is FAN on, high Z is off
+    modify_reg!(stm32ral::tim1, tim1, CCER, CC2E: 0, CC2NE: 1, CC2NP: 1);
+    //Main output enable of the advanced timer
+    modify_reg!(stm32ral::tim1, tim1, BDTR, MOE: 1);
+    modify_reg!(stm32ral::tim1, tim1, CR1, ARPE: Enabled, DIR: Up, CMS: EdgeAligned);
+    //Create an update event to auto reload the preload values
+    write_reg!(stm32ral::tim1, tim1, EGR, UG: Update);
+    modify_reg!(stm32ral::tim1, tim1, CR1, CEN: Enabled);
+
+    //select alternate function number AF1 for pin PB0 (TIM1_CH2N)
+    modify_reg!(stm32ral::gpio, gpio, AFRL, AFRL0: AF1);
+    //set alternate function mode for pin b0, still open drain with pull up
+    modify_reg!(stm32ral::gpio, gpio, MODER, MODER0: Alternate);
+}
+
+// duty out of DUTY_FULL, takes effect with the next PWM period
+pub fn set_duty(tim1: &stm32ral::tim1::Instance, duty: u32) {
+    modify_reg!(stm32ral::tim1, tim1, CCR2, CCR: duty.min(DUTY_FULL));
+}
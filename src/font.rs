@@ -0,0 +1,134 @@
+// Monospaced bitmap fonts for text on the cylinder.
+// The glyph tables are const so they stay in flash.
+// Each glyph is stored column by column, one u16 per column with bit 0 as the top row,
+// which is the same order the columns get sent out to the led driver.
+
+use crate::canvas::Canvas;
+
+mod glyphs_5x7;
+mod glyphs_8x12;
+
+pub struct Font {
+    pub width: usize,
+    pub height: usize,
+    // width * 95 columns for the printable ascii characters ' ' to '~'
+    glyphs: &'static [u16],
+}
+
+pub const FONT_5X7: Font = Font {
+    width: 5,
+    height: 7,
+    glyphs: &glyphs_5x7::GLYPHS,
+};
+
+pub const FONT_8X12: Font = Font {
+    width: 8,
+    height: 12,
+    glyphs: &glyphs_8x12::GLYPHS,
+};
+
+const FIRST: char = ' ';
+const LAST: char = '~';
+// drawn for everything we don't have a glyph for
+const REPLACEMENT: char = '?';
+
+impl Font {
+    // column bitmasks of a character, unknown characters map to '?'
+    pub fn glyph(&self, c: char) -> &'static [u16] {
+        let c = if (FIRST..=LAST).contains(&c) { c } else { REPLACEMENT };
+        let start = (c as usize - FIRST as usize) * self.width;
+        &self.glyphs[start..start + self.width]
+    }
+
+    // unclipped width of a text in columns
+    pub fn text_width(&self, text: &str) -> usize {
+        text.chars().count() * self.width
+    }
+
+    // first row so the glyphs sit vertically centered on a canvas with rows rows,
+    // fonts taller than the canvas start at the top and get clipped at the bottom
+    pub fn top_row(&self, rows: usize) -> usize {
+        rows.saturating_sub(self.height) / 2
+    }
+}
+
+// Draw one glyph column into a canvas column.
+// Only the rows covered by the font get written, lit pixels get color.
+pub fn draw_glyph_col<C: Canvas>(canvas: &mut C, font: &Font, col: usize, bits: u16, color: C::Color) {
+    let rows = canvas.rows();
+    let top = font.top_row(rows);
+    for row in 0..font.height.min(rows - top) {
+        let val = if bits & (1 << row) != 0 { color } else { C::OFF };
+        canvas.put_pixel(col, top + row, val);
+    }
+}
+
+// Draw a text starting at column col.
+// Everything beyond the last column gets clipped,
+// returns the number of columns actually drawn.
+pub fn draw_text<C: Canvas>(canvas: &mut C, font: &Font, col: usize, text: &str, color: C::Color) -> usize {
+    let mut x = col;
+    let cols = canvas.cols();
+    for c in text.chars() {
+        for &bits in font.glyph(c) {
+            if x >= cols {
+                return x - col;
+            }
+            draw_glyph_col(canvas, font, x, bits, color);
+            x += 1;
+        }
+    }
+    x - col
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dmabuffer::{DMAbuffer, COLS, ROWS};
+
+    fn lit(buf: &DMAbuffer, col: usize, row: usize) -> bool {
+        buf.pwm(col, row) != 0
+    }
+
+    #[test]
+    fn glyph_lookup() {
+        assert_eq!(FONT_5X7.glyph(' '), &[0; 5]);
+        assert_eq!(FONT_8X12.glyph('~').len(), 8);
+        assert_eq!(FONT_5X7.glyph('\u{e4}'), FONT_5X7.glyph('?'));
+        assert_eq!(FONT_5X7.glyph('\n'), FONT_5X7.glyph('?'));
+        // glyphs never use rows beyond the font height
+        assert!(FONT_5X7.glyphs.iter().all(|&c| c >> 7 == 0));
+        assert!(FONT_8X12.glyphs.iter().all(|&c| c >> 12 == 0));
+    }
+
+    #[test]
+    fn draw_text_returns_width() {
+        let mut buf = DMAbuffer::new();
+        assert_eq!(draw_text(&mut buf, &FONT_5X7, 10, "Hi!", 255), 15);
+        assert_eq!(draw_text(&mut buf, &FONT_8X12, 0, "Hi", 255), 16);
+        assert_eq!(draw_text(&mut buf, &FONT_8X12, 0, "", 255), 0);
+    }
+
+    #[test]
+    fn draw_text_renders_glyph_bits() {
+        let mut buf = DMAbuffer::new();
+        draw_text(&mut buf, &FONT_5X7, 3, "I", 255);
+        let top = FONT_5X7.top_row(ROWS);
+        for (i, &bits) in FONT_5X7.glyph('I').iter().enumerate() {
+            for row in 0..7 {
+                assert_eq!(lit(&buf, 3 + i, top + row), bits & (1 << row) != 0);
+            }
+        }
+        // rows outside the font are left alone
+        assert!(!lit(&buf, 5, 0));
+        assert!(!lit(&buf, 5, ROWS - 1));
+    }
+
+    #[test]
+    fn draw_text_clips_at_last_column() {
+        let mut buf = DMAbuffer::new();
+        assert_eq!(draw_text(&mut buf, &FONT_8X12, COLS - 10, "MMM", 255), 10);
+        assert_eq!(draw_text(&mut buf, &FONT_8X12, COLS, "M", 255), 0);
+        assert_eq!(draw_text(&mut buf, &FONT_8X12, COLS + 5, "M", 255), 0);
+    }
+}
@@ -0,0 +1,100 @@
+// 5x7 glyphs for ' ' to '~', converted from the public domain
+// X11 misc-fixed 5x7 font.
+// One u16 per column, bit 0 is the top row.
+pub const GLYPHS: [u16; 475] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, // ' '
+    0x00, 0x00, 0x2f, 0x00, 0x00, // '!'
+    0x00, 0x07, 0x00, 0x07, 0x00, // '"'
+    0x14, 0x3e, 0x14, 0x3e, 0x14, // '#'
+    0x04, 0x2a, 0x3e, 0x2a, 0x10, // '$'
+    0x13, 0x08, 0x04, 0x32, 0x00, // '%'
+    0x14, 0x2a, 0x14, 0x20, 0x00, // '&'
+    0x00, 0x00, 0x07, 0x00, 0x00, // '\''
+    0x00, 0x1e, 0x21, 0x00, 0x00, // '('
+    0x00, 0x21, 0x1e, 0x00, 0x00, // ')'
+    0x00, 0x2a, 0x1c, 0x2a, 0x00, // '*'
+    0x08, 0x08, 0x3e, 0x08, 0x08, // '+'
+    0x00, 0x40, 0x30, 0x10, 0x00, // ','
+    0x08, 0x08, 0x08, 0x08, 0x00, // '-'
+    0x00, 0x30, 0x30, 0x00, 0x00, // '.'
+    0x10, 0x08, 0x04, 0x02, 0x00, // '/'
+    0x00, 0x1e, 0x21, 0x1e, 0x00, // '0'
+    0x00, 0x22, 0x3f, 0x20, 0x00, // '1'
+    0x22, 0x31, 0x29, 0x26, 0x00, // '2'
+    0x11, 0x25, 0x25, 0x1b, 0x00, // '3'
+    0x0c, 0x0a, 0x3f, 0x08, 0x00, // '4'
+    0x17, 0x25, 0x25, 0x19, 0x00, // '5'
+    0x1e, 0x25, 0x25, 0x18, 0x00, // '6'
+    0x01, 0x31, 0x0d, 0x03, 0x00, // '7'
+    0x1a, 0x25, 0x25, 0x1a, 0x00, // '8'
+    0x06, 0x29, 0x29, 0x1e, 0x00, // '9'
+    0x00, 0x36, 0x36, 0x00, 0x00, // ':'
+    0x40, 0x36, 0x16, 0x00, 0x00, // ';'
+    0x00, 0x08, 0x14, 0x22, 0x00, // '<'
+    0x14, 0x14, 0x14, 0x14, 0x00, // '='
+    0x00, 0x22, 0x14, 0x08, 0x00, // '>'
+    0x00, 0x02, 0x29, 0x06, 0x00, // '?'
+    0x1e, 0x21, 0x2d, 0x0e, 0x00, // '@'
+    0x3e, 0x09, 0x09, 0x3e, 0x00, // 'A'
+    0x3f, 0x25, 0x25, 0x1a, 0x00, // 'B'
+    0x1e, 0x21, 0x21, 0x12, 0x00, // 'C'
+    0x3f, 0x21, 0x21, 0x1e, 0x00, // 'D'
+    0x3f, 0x25, 0x25, 0x21, 0x00, // 'E'
+    0x3f, 0x05, 0x05, 0x01, 0x00, // 'F'
+    0x1e, 0x21, 0x29, 0x3a, 0x00, // 'G'
+    0x3f, 0x04, 0x04, 0x3f, 0x00, // 'H'
+    0x00, 0x21, 0x3f, 0x21, 0x00, // 'I'
+    0x10, 0x20, 0x20, 0x1f, 0x00, // 'J'
+    0x3f, 0x0c, 0x12, 0x21, 0x00, // 'K'
+    0x3f, 0x20, 0x20, 0x20, 0x00, // 'L'
+    0x3f, 0x06, 0x06, 0x3f, 0x00, // 'M'
+    0x3f, 0x06, 0x18, 0x3f, 0x00, // 'N'
+    0x1e, 0x21, 0x21, 0x1e, 0x00, // 'O'
+    0x3f, 0x09, 0x09, 0x06, 0x00, // 'P'
+    0x1e, 0x31, 0x21, 0x5e, 0x00, // 'Q'
+    0x3f, 0x09, 0x19, 0x26, 0x00, // 'R'
+    0x12, 0x25, 0x29, 0x12, 0x00, // 'S'
+    0x00, 0x01, 0x3f, 0x01, 0x00, // 'T'
+    0x1f, 0x20, 0x20, 0x1f, 0x00, // 'U'
+    0x0f, 0x30, 0x30, 0x0f, 0x00, // 'V'
+    0x3f, 0x18, 0x18, 0x3f, 0x00, // 'W'
+    0x33, 0x0c, 0x0c, 0x33, 0x00, // 'X'
+    0x00, 0x07, 0x38, 0x07, 0x00, // 'Y'
+    0x31, 0x29, 0x25, 0x23, 0x00, // 'Z'
+    0x00, 0x3f, 0x21, 0x21, 0x00, // '['
+    0x02, 0x04, 0x08, 0x10, 0x00, // '\\'
+    0x00, 0x21, 0x21, 0x3f, 0x00, // ']'
+    0x00, 0x02, 0x01, 0x02, 0x00, // '^'
+    0x20, 0x20, 0x20, 0x20, 0x00, // '_'
+    0x00, 0x01, 0x02, 0x00, 0x00, // '`'
+    0x18, 0x24, 0x14, 0x3c, 0x00, // 'a'
+    0x3f, 0x24, 0x24, 0x18, 0x00, // 'b'
+    0x18, 0x24, 0x24, 0x00, 0x00, // 'c'
+    0x18, 0x24, 0x24, 0x3f, 0x00, // 'd'
+    0x18, 0x34, 0x2c, 0x08, 0x00, // 'e'
+    0x08, 0x3e, 0x09, 0x02, 0x00, // 'f'
+    0x28, 0x54, 0x54, 0x4c, 0x00, // 'g'
+    0x3f, 0x04, 0x04, 0x38, 0x00, // 'h'
+    0x00, 0x24, 0x3d, 0x20, 0x00, // 'i'
+    0x00, 0x20, 0x40, 0x3d, 0x00, // 'j'
+    0x3f, 0x08, 0x14, 0x20, 0x00, // 'k'
+    0x00, 0x21, 0x3f, 0x20, 0x00, // 'l'
+    0x3c, 0x08, 0x0c, 0x38, 0x00, // 'm'
+    0x3c, 0x04, 0x04, 0x38, 0x00, // 'n'
+    0x18, 0x24, 0x24, 0x18, 0x00, // 'o'
+    0x7c, 0x24, 0x24, 0x18, 0x00, // 'p'
+    0x18, 0x24, 0x24, 0x7c, 0x00, // 'q'
+    0x3c, 0x04, 0x04, 0x08, 0x00, // 'r'
+    0x28, 0x2c, 0x34, 0x14, 0x00, // 's'
+    0x04, 0x1f, 0x24, 0x20, 0x00, // 't'
+    0x1c, 0x20, 0x20, 0x3c, 0x00, // 'u'
+    0x00, 0x1c, 0x20, 0x1c, 0x00, // 'v'
+    0x3c, 0x30, 0x30, 0x3c, 0x00, // 'w'
+    0x24, 0x18, 0x18, 0x24, 0x00, // 'x'
+    0x0c, 0x50, 0x20, 0x1c, 0x00, // 'y'
+    0x24, 0x34, 0x2c, 0x24, 0x00, // 'z'
+    0x00, 0x04, 0x1e, 0x21, 0x00, // '{'
+    0x00, 0x00, 0x3f, 0x00, 0x00, // '|'
+    0x00, 0x21, 0x1e, 0x04, 0x00, // '}'
+    0x02, 0x01, 0x02, 0x01, 0x00, // '~'
+];
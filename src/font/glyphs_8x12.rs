@@ -0,0 +1,100 @@
+// 8x12 glyphs for ' ' to '~', converted from the public domain
+// X11 misc-fixed 8x13 (blank top row removed) font.
+// One u16 per column, bit 0 is the top row.
+pub const GLYPHS: [u16; 760] = [
+    0x000, 0x000, 0x000, 0x000, 0x000, 0x000, 0x000, 0x000, // ' '
+    0x000, 0x000, 0x000, 0x2fe, 0x000, 0x000, 0x000, 0x000, // '!'
+    0x000, 0x000, 0x00e, 0x000, 0x000, 0x00e, 0x000, 0x000, // '"'
+    0x000, 0x050, 0x1fc, 0x050, 0x050, 0x1fc, 0x050, 0x000, // '#'
+    0x000, 0x118, 0x124, 0x3fe, 0x124, 0x0c4, 0x000, 0x000, // '$'
+    0x000, 0x204, 0x18a, 0x044, 0x130, 0x288, 0x106, 0x000, // '%'
+    0x000, 0x1b0, 0x248, 0x248, 0x2b0, 0x100, 0x280, 0x000, // '&'
+    0x000, 0x000, 0x000, 0x00e, 0x000, 0x000, 0x000, 0x000, // '\''
+    0x000, 0x000, 0x000, 0x070, 0x18c, 0x202, 0x000, 0x000, // '('
+    0x000, 0x000, 0x202, 0x18c, 0x070, 0x000, 0x000, 0x000, // ')'
+    0x000, 0x008, 0x02a, 0x01c, 0x01c, 0x02a, 0x008, 0x000, // '*'
+    0x000, 0x020, 0x020, 0x0f8, 0x020, 0x020, 0x000, 0x000, // '+'
+    0x000, 0x400, 0x300, 0x300, 0x100, 0x000, 0x000, 0x000, // ','
+    0x000, 0x020, 0x020, 0x020, 0x020, 0x020, 0x000, 0x000, // '-'
+    0x000, 0x000, 0x200, 0x700, 0x200, 0x000, 0x000, 0x000, // '.'
+    0x300, 0x080, 0x040, 0x020, 0x010, 0x008, 0x006, 0x000, // '/'
+    0x000, 0x0f8, 0x104, 0x202, 0x202, 0x104, 0x0f8, 0x000, // '0'
+    0x000, 0x208, 0x204, 0x3fe, 0x200, 0x200, 0x000, 0x000, // '1'
+    0x000, 0x30c, 0x282, 0x242, 0x242, 0x222, 0x21c, 0x000, // '2'
+    0x000, 0x102, 0x202, 0x222, 0x232, 0x22a, 0x1c6, 0x000, // '3'
+    0x000, 0x0e0, 0x090, 0x088, 0x084, 0x3fe, 0x080, 0x000, // '4'
+    0x000, 0x13e, 0x222, 0x212, 0x212, 0x212, 0x1e2, 0x000, // '5'
+    0x000, 0x1f8, 0x244, 0x222, 0x222, 0x222, 0x1c0, 0x000, // '6'
+    0x000, 0x002, 0x302, 0x0c2, 0x032, 0x00a, 0x006, 0x000, // '7'
+    0x000, 0x1dc, 0x222, 0x222, 0x222, 0x222, 0x1dc, 0x000, // '8'
+    0x000, 0x01c, 0x222, 0x222, 0x222, 0x112, 0x0fc, 0x000, // '9'
+    0x000, 0x000, 0x210, 0x738, 0x210, 0x000, 0x000, 0x000, // ':'
+    0x000, 0x400, 0x310, 0x338, 0x110, 0x000, 0x000, 0x000, // ';'
+    0x000, 0x000, 0x020, 0x050, 0x088, 0x104, 0x202, 0x000, // '<'
+    0x000, 0x090, 0x090, 0x090, 0x090, 0x090, 0x090, 0x000, // '='
+    0x000, 0x202, 0x104, 0x088, 0x050, 0x020, 0x000, 0x000, // '>'
+    0x000, 0x00c, 0x002, 0x002, 0x2c2, 0x022, 0x01c, 0x000, // '?'
+    0x000, 0x1fc, 0x202, 0x262, 0x292, 0x252, 0x0fc, 0x000, // '@'
+    0x000, 0x3f8, 0x044, 0x042, 0x042, 0x044, 0x3f8, 0x000, // 'A'
+    0x000, 0x3fe, 0x222, 0x222, 0x222, 0x154, 0x088, 0x000, // 'B'
+    0x000, 0x1fc, 0x202, 0x202, 0x202, 0x202, 0x104, 0x000, // 'C'
+    0x000, 0x3fe, 0x202, 0x202, 0x202, 0x104, 0x0f8, 0x000, // 'D'
+    0x000, 0x3fe, 0x222, 0x222, 0x222, 0x202, 0x202, 0x000, // 'E'
+    0x000, 0x3fe, 0x022, 0x022, 0x022, 0x002, 0x002, 0x000, // 'F'
+    0x000, 0x1fc, 0x202, 0x202, 0x242, 0x142, 0x3c4, 0x000, // 'G'
+    0x000, 0x3fe, 0x020, 0x020, 0x020, 0x020, 0x3fe, 0x000, // 'H'
+    0x000, 0x202, 0x202, 0x3fe, 0x202, 0x202, 0x000, 0x000, // 'I'
+    0x000, 0x100, 0x200, 0x202, 0x202, 0x1fe, 0x002, 0x002, // 'J'
+    0x000, 0x3fe, 0x020, 0x050, 0x088, 0x104, 0x202, 0x000, // 'K'
+    0x000, 0x3fe, 0x200, 0x200, 0x200, 0x200, 0x200, 0x000, // 'L'
+    0x3fe, 0x008, 0x010, 0x060, 0x010, 0x008, 0x3fe, 0x000, // 'M'
+    0x000, 0x3fe, 0x008, 0x010, 0x020, 0x040, 0x3fe, 0x000, // 'N'
+    0x000, 0x1fc, 0x202, 0x202, 0x202, 0x202, 0x1fc, 0x000, // 'O'
+    0x000, 0x3fe, 0x022, 0x022, 0x022, 0x022, 0x01c, 0x000, // 'P'
+    0x000, 0x1fc, 0x202, 0x282, 0x302, 0x202, 0x5fc, 0x000, // 'Q'
+    0x000, 0x3fe, 0x022, 0x062, 0x0a2, 0x122, 0x21c, 0x000, // 'R'
+    0x000, 0x11c, 0x222, 0x222, 0x222, 0x222, 0x1c4, 0x000, // 'S'
+    0x002, 0x002, 0x002, 0x3fe, 0x002, 0x002, 0x002, 0x000, // 'T'
+    0x000, 0x1fe, 0x200, 0x200, 0x200, 0x200, 0x1fe, 0x000, // 'U'
+    0x006, 0x038, 0x1c0, 0x200, 0x1c0, 0x038, 0x006, 0x000, // 'V'
+    0x1fe, 0x200, 0x100, 0x0e0, 0x100, 0x200, 0x1fe, 0x000, // 'W'
+    0x306, 0x088, 0x050, 0x020, 0x050, 0x088, 0x306, 0x000, // 'X'
+    0x006, 0x008, 0x010, 0x3e0, 0x010, 0x008, 0x006, 0x000, // 'Y'
+    0x000, 0x382, 0x242, 0x222, 0x212, 0x20a, 0x206, 0x000, // 'Z'
+    0x000, 0x000, 0x3fe, 0x202, 0x202, 0x202, 0x000, 0x000, // '['
+    0x006, 0x008, 0x010, 0x020, 0x040, 0x080, 0x300, 0x000, // '\\'
+    0x000, 0x202, 0x202, 0x202, 0x3fe, 0x000, 0x000, 0x000, // ']'
+    0x000, 0x008, 0x004, 0x002, 0x004, 0x008, 0x000, 0x000, // '^'
+    0x400, 0x400, 0x400, 0x400, 0x400, 0x400, 0x400, 0x000, // '_'
+    0x000, 0x000, 0x000, 0x001, 0x002, 0x000, 0x000, 0x000, // '`'
+    0x000, 0x180, 0x250, 0x250, 0x250, 0x150, 0x3e0, 0x000, // 'a'
+    0x000, 0x3fe, 0x120, 0x210, 0x210, 0x210, 0x1e0, 0x000, // 'b'
+    0x000, 0x1e0, 0x210, 0x210, 0x210, 0x210, 0x120, 0x000, // 'c'
+    0x000, 0x1e0, 0x210, 0x210, 0x210, 0x120, 0x3fe, 0x000, // 'd'
+    0x000, 0x1e0, 0x250, 0x250, 0x250, 0x250, 0x160, 0x000, // 'e'
+    0x000, 0x020, 0x3fc, 0x022, 0x022, 0x022, 0x004, 0x000, // 'f'
+    0x000, 0x560, 0xa90, 0xa90, 0xa90, 0xa60, 0x410, 0x000, // 'g'
+    0x000, 0x3fe, 0x020, 0x010, 0x010, 0x010, 0x3e0, 0x000, // 'h'
+    0x000, 0x200, 0x210, 0x3f4, 0x200, 0x200, 0x000, 0x000, // 'i'
+    0x000, 0x600, 0x800, 0x800, 0x810, 0x7f4, 0x000, 0x000, // 'j'
+    0x000, 0x3fe, 0x040, 0x040, 0x0a0, 0x110, 0x200, 0x000, // 'k'
+    0x000, 0x200, 0x202, 0x3fe, 0x200, 0x200, 0x000, 0x000, // 'l'
+    0x3f0, 0x010, 0x010, 0x1e0, 0x010, 0x010, 0x3e0, 0x000, // 'm'
+    0x000, 0x3f0, 0x020, 0x010, 0x010, 0x010, 0x3e0, 0x000, // 'n'
+    0x000, 0x1e0, 0x210, 0x210, 0x210, 0x210, 0x1e0, 0x000, // 'o'
+    0x000, 0xff0, 0x0a0, 0x110, 0x110, 0x110, 0x0e0, 0x000, // 'p'
+    0x000, 0x0e0, 0x110, 0x110, 0x110, 0x0a0, 0xff0, 0x000, // 'q'
+    0x000, 0x010, 0x3e0, 0x010, 0x010, 0x010, 0x020, 0x000, // 'r'
+    0x000, 0x120, 0x250, 0x250, 0x290, 0x290, 0x120, 0x000, // 's'
+    0x000, 0x010, 0x1fc, 0x210, 0x210, 0x210, 0x100, 0x000, // 't'
+    0x000, 0x1f0, 0x200, 0x200, 0x200, 0x1f0, 0x200, 0x000, // 'u'
+    0x000, 0x070, 0x180, 0x200, 0x180, 0x070, 0x000, 0x000, // 'v'
+    0x1f0, 0x200, 0x100, 0x0c0, 0x100, 0x200, 0x1f0, 0x000, // 'w'
+    0x000, 0x210, 0x120, 0x0c0, 0x0c0, 0x120, 0x210, 0x000, // 'x'
+    0x000, 0x4f0, 0x900, 0x900, 0x900, 0x880, 0x7f0, 0x000, // 'y'
+    0x000, 0x210, 0x310, 0x290, 0x250, 0x230, 0x210, 0x000, // 'z'
+    0x000, 0x000, 0x020, 0x1ac, 0x252, 0x202, 0x202, 0x000, // '{'
+    0x000, 0x000, 0x000, 0x3fe, 0x000, 0x000, 0x000, 0x000, // '|'
+    0x000, 0x202, 0x202, 0x252, 0x1ac, 0x020, 0x000, 0x000, // '}'
+    0x000, 0x00c, 0x002, 0x004, 0x008, 0x006, 0x000, 0x000, // '~'
+];
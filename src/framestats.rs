@@ -0,0 +1,128 @@
+// Frame delivery statistics of the triple buffer.
+// The idle task counts the frames it submits, the DMA interrupt the ones it picks up for display
+// and the transfers it has to repeat the active frame since no new one was ready (the renderer was late).
+// Submitted frames never get dropped, the triple buffer picks up the oldest ready one and doesn't
+// replace a queued frame with a newer one. dropped() derives the count anyway, so the log shows it.
+// Timestamps are DWT cycle counts of the last event of each kind.
+
+use crate::dmaflags::{Counters, Event, TCIF};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameStats {
+    pub submitted: u32,
+    pub displayed: u32,
+    pub repeated: u32,
+    // transfer complete and error interrupts of the DMA stream
+    pub dma: Counters,
+    pub last_submitted: u32,
+    pub last_displayed: u32,
+    pub last_repeated: u32,
+    pub last_dma_error: u32,
+    // cycles the last frame took to render and the longest so far
+    pub render_cycles: u32,
+    pub max_render_cycles: u32,
+}
+
+impl FrameStats {
+    pub const fn new() -> Self {
+        FrameStats {
+            submitted: 0,
+            displayed: 0,
+            repeated: 0,
+            dma: Counters::new(),
+            last_submitted: 0,
+            last_displayed: 0,
+            last_repeated: 0,
+            last_dma_error: 0,
+            render_cycles: 0,
+            max_render_cycles: 0,
+        }
+    }
+
+    // a rendered frame went to the DMA side
+    pub fn submit(&mut self, now: u32, render_cycles: u32) {
+        self.submitted = self.submitted.wrapping_add(1);
+        self.last_submitted = now;
+        self.render_cycles = render_cycles;
+        self.max_render_cycles = self.max_render_cycles.max(render_cycles);
+    }
+
+    // a transfer completed, fresh if the next one sends a new frame, otherwise the active one repeats
+    pub fn transfer(&mut self, now: u32, fresh: bool) {
+        if fresh {
+            self.displayed = self.displayed.wrapping_add(1);
+            self.last_displayed = now;
+        } else {
+            self.repeated = self.repeated.wrapping_add(1);
+            self.last_repeated = now;
+        }
+    }
+
+    // Count the flags of a DMA interrupt and tell what it means.
+    // The flags of a stopped stream don't count, they came from stopping it
+    pub fn dma_interrupt(&mut self, now: u32, flags: u32, stopped: bool) -> Event {
+        if stopped {
+            Event::Ignore
+        } else if self.dma.count(flags) {
+            self.last_dma_error = now;
+            Event::Error
+        } else if flags & TCIF != 0 {
+            Event::Complete
+        } else {
+            Event::Ignore
+        }
+    }
+
+    pub fn dma_errors(&self) -> u32 {
+        self.dma.errors()
+    }
+
+    // frames submitted but not picked up by the DMA yet
+    pub fn pending(&self) -> u32 {
+        self.submitted.wrapping_sub(self.displayed)
+    }
+
+    // submitted frames that were neither displayed nor are queued, with the ready buffers
+    // of the triple buffer taken at the same time as the counters
+    pub fn dropped(&self, queued: u32) -> u32 {
+        self.pending().wrapping_sub(queued)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dmaflags::{FEIF, TEIF};
+
+    #[test]
+    fn counts_frames() {
+        let mut stats = FrameStats::new();
+        stats.submit(100, 40);
+        stats.submit(200, 60);
+        assert_eq!(stats.pending(), 2);
+        stats.transfer(250, true);
+        stats.transfer(300, true);
+        stats.transfer(350, false);
+        assert_eq!((stats.submitted, stats.displayed, stats.repeated), (2, 2, 1));
+        assert_eq!((stats.last_submitted, stats.last_displayed, stats.last_repeated), (200, 300, 350));
+        assert_eq!((stats.render_cycles, stats.max_render_cycles), (60, 60));
+        stats.submit(400, 10);
+        assert_eq!((stats.render_cycles, stats.max_render_cycles), (10, 60));
+        assert_eq!(stats.pending(), 1);
+        assert_eq!(stats.dropped(1), 0);
+        assert_eq!(stats.dropped(0), 1);
+    }
+
+    #[test]
+    fn counts_dma_errors() {
+        let mut stats = FrameStats::new();
+        assert_eq!(stats.dma_interrupt(10, TCIF, false), Event::Complete);
+        assert_eq!(stats.dma_interrupt(20, TEIF, false), Event::Error);
+        assert_eq!(stats.dma_interrupt(30, FEIF, false), Event::Error);
+        assert_eq!(stats.dma_interrupt(40, TCIF, false), Event::Complete);
+        assert_eq!(stats.dma_interrupt(50, 0, false), Event::Ignore);
+        assert_eq!(stats.dma_errors(), 2);
+        assert_eq!(stats.dma.complete, 2);
+        assert_eq!(stats.last_dma_error, 30);
+    }
+}
@@ -0,0 +1,52 @@
+// 8 bit brightness to 16 bit PWM value tables, generated by build.rs.
+// GAMMA uses the exponent from MINIPOV_GAMMA (default 2.8),
+// CIE_LIGHTNESS follows the CIE 1931 lightness curve.
+// GAMMA_RED, GAMMA_GREEN and GAMMA_BLUE are the per colour curves of the RGB mode,
+// their exponents default to the one of GAMMA.
+include!(concat!(env!("OUT_DIR"), "/gamma.rs"));
+
+// the curve used for pixels, select the CIE curve with the cie-lightness feature
+#[cfg(not(feature = "cie-lightness"))]
+pub const CURVE: &[u16; 256] = &GAMMA;
+#[cfg(feature = "cie-lightness")]
+pub const CURVE: &[u16; 256] = &CIE_LIGHTNESS;
+
+// per colour curves of the RGB mode
+#[cfg(not(feature = "cie-lightness"))]
+pub const CURVE_RGB: [&[u16; 256]; 3] = [&GAMMA_RED, &GAMMA_GREEN, &GAMMA_BLUE];
+#[cfg(feature = "cie-lightness")]
+pub const CURVE_RGB: [&[u16; 256]; 3] = [&CIE_LIGHTNESS; 3];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tables_span_full_pwm_range() {
+        for table in [&GAMMA, &CIE_LIGHTNESS, &GAMMA_RED, &GAMMA_GREEN, &GAMMA_BLUE].iter() {
+            assert_eq!(table[0], 0);
+            assert_eq!(table[255], 0xFFFF);
+            assert!(table.windows(2).all(|w| w[0] <= w[1]));
+        }
+    }
+
+    #[test]
+    fn default_exponent_matches_old_16_level_table() {
+        if GAMMA_EXPONENT != 2.8 {
+            return;
+        }
+        let old = [0, 33, 232, 723, 1619, 3024, 5038, 7757,
+                   11274, 15678, 21058, 27499, 35085, 43899, 54023, 65535];
+        for (i, &val) in old.iter().enumerate() {
+            assert_eq!(GAMMA[i * 17], val);
+        }
+    }
+
+    #[test]
+    fn cie_lightness_is_linear_near_black() {
+        // L* = 100/255 is below the linear threshold of 8
+        assert_eq!(CIE_LIGHTNESS[1], (65535.0 * 100.0 / 255.0 / 903.3 + 0.5) as u16);
+        // perceived mid grey is about 18% luminance
+        assert!((CIE_LIGHTNESS[128] as f32 / 65535.0 - 0.18).abs() < 0.01);
+    }
+}
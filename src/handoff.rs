@@ -0,0 +1,57 @@
+// Buffer handoff decision of the DMA transfer complete interrupt.
+// Three buffers are passed around as raw addresses:
+// one is active in the DMA, one is queued as the next (idle) DMA target
+// and one is rendered by the idle task.
+
+pub struct Handoff {
+    // buffer handed back to the idle task, if any
+    pub release: Option<u32>,
+    // buffer to program as the next (currently idle) DMA target
+    pub idle_target: u32,
+}
+
+// finished: the buffer the DMA just finished sending
+// active: the buffer the DMA switched to
+// next: a freshly rendered buffer from the idle task, if there is one
+pub fn schedule(finished: u32, active: u32, next: Option<u32>) -> Handoff {
+    // hand the finished buffer back to idle if it is not the same buffer
+    // as the currently active one (was not re-scheduled)
+    let release = if finished != active { Some(finished) } else { None };
+    // without new data we re-schedule the currently active buffer
+    let idle_target = next.unwrap_or(active);
+    Handoff { release, idle_target }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: u32 = 0x2000_0000;
+    const B: u32 = 0x2000_1000;
+    const C: u32 = 0x2000_2000;
+
+    #[test]
+    fn new_buffer_is_scheduled_and_finished_released() {
+        let h = schedule(A, B, Some(C));
+        assert_eq!(h.release, Some(A));
+        assert_eq!(h.idle_target, C);
+    }
+
+    #[test]
+    fn underrun_reschedules_active_buffer() {
+        let h = schedule(A, B, None);
+        assert_eq!(h.release, Some(A));
+        assert_eq!(h.idle_target, B);
+        // the next transfer complete finishes B and switches to B again
+        let h = schedule(B, B, None);
+        assert_eq!(h.release, None);
+        assert_eq!(h.idle_target, B);
+    }
+
+    #[test]
+    fn recovery_after_underrun_does_not_release_active() {
+        let h = schedule(B, B, Some(A));
+        assert_eq!(h.release, None);
+        assert_eq!(h.idle_target, A);
+    }
+}
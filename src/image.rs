@@ -0,0 +1,74 @@
+// Grayscale images in flash, one brightness (0..=255) per pixel.
+// Column-major like the DMA buffer: the rows of column 0 first, then column 1 ...
+// Images narrower than the canvas repeat around the cylinder, rows beyond the image stay off.
+
+use crate::canvas::{Canvas, Dim};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Image {
+    pub cols: usize,
+    pub rows: usize,
+    pub pixels: &'static [u8],
+}
+
+impl Image {
+    pub fn pixel(&self, col: usize, row: usize) -> u8 {
+        self.pixels[col * self.rows + row]
+    }
+
+    // Fill the whole canvas, every pixel is color dimmed by the image brightness
+    pub fn draw<C: Canvas>(&self, canvas: &mut C, color: C::Color)
+    where
+        C::Color: Dim,
+    {
+        let rows = self.rows.min(canvas.rows());
+        for col in 0..canvas.cols() {
+            canvas.clear_col(col);
+            if self.cols == 0 {
+                continue;
+            }
+            let src = col % self.cols;
+            for row in 0..rows {
+                let brightness = self.pixel(src, row);
+                if brightness != 0 {
+                    canvas.put_pixel(col, row, color.dim(brightness));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dmabuffer::{DMAbuffer, COLS, ROWS};
+
+    // two columns, 3 rows
+    const STRIPES: Image = Image {
+        cols: 2,
+        rows: 3,
+        pixels: &[255, 0, 255, 0, 128, 0],
+    };
+
+    #[test]
+    fn narrow_images_repeat() {
+        let mut buf = DMAbuffer::new();
+        STRIPES.draw(&mut buf, 255);
+        for col in 0..COLS {
+            let lit: Vec<bool> = (0..ROWS).map(|row| buf.pwm(col, row) != 0).collect();
+            let expected = if col % 2 == 0 { [true, false, true] } else { [false, true, false] };
+            assert_eq!(&lit[..3], &expected);
+            assert!(lit[3..].iter().all(|&on| !on));
+        }
+        // half brightness is less than full
+        assert!(buf.pwm(1, 1) < buf.pwm(0, 0));
+    }
+
+    #[test]
+    fn redraw_clears_the_old_image() {
+        let mut buf = DMAbuffer::new();
+        STRIPES.draw(&mut buf, 255);
+        STRIPES.draw(&mut buf, 0);
+        assert!((0..COLS).all(|col| (0..ROWS).all(|row| buf.pwm(col, row) == 0)));
+    }
+}
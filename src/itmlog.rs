@@ -0,0 +1,57 @@
+// Firmware side of the binary log (see minipov::log), every module writes its records to its own
+// stimulus port log::port(module). Ports the debugger didn't enable in TER cost a register read.
+// The Logger owns the ITM and is an rtfm resource: a record goes out under its lock, so records don't
+// interleave and the tasks above its ceiling (tacho and image end) never wait for the FIFO. They don't log.
+// log!(logger, msg::DMA_ERROR, errors, fifo) costs a filter lookup and a few ITM writes, no formatting.
+use cortex_m::peripheral::{itm, DWT, ITM};
+
+use minipov::log::{self, Filter, Level::*, Message};
+
+// Max. level per module, in the order of log::Module:
+// Init, Clocks, Dma, Frames, Fan, Watchdog
+pub const FILTER: Filter = Filter {
+    levels: [Info, Info, Info, Info, Info, Info],
+};
+
+// TCR.ITMENA
+const ITMENA: u32 = 1;
+
+// log!(logger, msg, args...) with a &mut Logger, lock the resource first
+macro_rules! log {
+    ($logger:expr, $msg:expr $(, $arg:expr)*) => {
+        crate::itmlog::Logger::write($logger, &$msg, &[$($arg as u32),*])
+    };
+}
+
+pub struct Logger {
+    itm: ITM,
+}
+
+impl Logger {
+    pub fn new(itm: ITM) -> Self {
+        Logger { itm }
+    }
+
+    pub fn write(&mut self, msg: &Message, args: &[u32]) {
+        if !FILTER.enabled(msg) {
+            return;
+        }
+        debug_assert_eq!(args.len(), log::arg_count(msg.format));
+        let port = log::port(msg.module);
+        // no debugger listening or the port of the module is off, the FIFO would never drain
+        if self.itm.tcr.read() & ITMENA == 0 || self.itm.ter[0].read() & (1 << port) == 0 {
+            return;
+        }
+        let stim = &mut self.itm.stim[port];
+        put(stim, log::header(msg, args.len()));
+        put(stim, DWT::get_cycle_count());
+        for &arg in args {
+            put(stim, arg);
+        }
+    }
+}
+
+fn put(stim: &mut itm::Stim, word: u32) {
+    while !stim.is_fifo_ready() {}
+    stim.write_u32(word);
+}
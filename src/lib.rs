@@ -0,0 +1,31 @@
+// Hardware independent part of the mini-pov firmware.
+// Everything in here builds for the mcu and for the host,
+// so the pixel encoding and buffer logic can be tested with
+// cargo test --lib --target x86_64-unknown-linux-gnu
+#![cfg_attr(not(test), no_std)]
+
+pub mod animation;
+pub mod assets;
+pub mod canvas;
+pub mod clocks;
+pub mod coltiming;
+pub mod dmabuffer;
+pub mod dmaflags;
+pub mod fan;
+pub mod font;
+pub mod framestats;
+pub mod gamma;
+pub mod handoff;
+pub mod image;
+pub mod log;
+pub mod marquee;
+pub mod phase;
+pub mod pulses;
+pub mod rgb;
+pub mod tacho;
+pub mod timing;
+pub mod triplebuffer;
+pub mod tlc59711;
+pub mod watchdog;
+
+pub use dmabuffer::{DMAbuffer, BUFLEN, COLS, DRIVERS, ROWS, U16PERROW};
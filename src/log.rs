@@ -0,0 +1,570 @@
+// Binary logging over ITM.
+// Formatting text takes too long in the interrupt handlers, so a log record only carries
+// the index of its message in MESSAGES (the interned format string), a DWT timestamp and
+// the raw u32 arguments. Every module logs on its own stimulus port (port(module)),
+// so the debugger turns modules on and off through TER and the host can pick them by port.
+// Port 0 keeps the text of iprintln and panics.
+// The host decoder (src/bin/itmdecode.rs) is built from the same MESSAGES and turns
+// the ITM stream back into readable lines.
+//
+// A record on the wire, u32 words:
+//   header: SYNC << 24 | number of args << 16 | message index
+//   timestamp
+//   args...
+//
+// Placeholders in the formats: {} decimal, {i} signed, {x} hex,
+// {=A|B|C} the name at index arg for enums.
+
+use core::fmt;
+
+// port of the first module, the others follow in the order of Module
+pub const FIRST_PORT: usize = 1;
+// top byte of a header, lets the decoder find the next record after lost words
+pub const SYNC: u32 = 0xA5;
+pub const MAX_ARGS: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Module {
+    Init,
+    Clocks,
+    Dma,
+    Frames,
+    Fan,
+    Watchdog,
+}
+pub const MODULES: usize = 6;
+
+impl Module {
+    pub const ALL: [Module; MODULES] = [Module::Init, Module::Clocks, Module::Dma, Module::Frames, Module::Fan, Module::Watchdog];
+}
+
+// stimulus port the records of a module go to
+pub const fn port(module: Module) -> usize {
+    FIRST_PORT + module as usize
+}
+
+pub struct Message {
+    pub id: u16,
+    pub level: Level,
+    pub module: Module,
+    pub format: &'static str,
+}
+
+// The message catalog, id has to be the index in MESSAGES
+pub mod msg {
+    use super::{Level::*, Message, Module::*};
+
+    pub const CLOCK_HSE: Message = Message {
+        id: 0,
+        level: Info,
+        module: Clocks,
+        format: "running from the crystal",
+    };
+    pub const CLOCK_HSI: Message = Message {
+        id: 1,
+        level: Warn,
+        module: Clocks,
+        format: "the crystal didn't start, running from the HSI",
+    };
+    pub const INIT_FAILED: Message = Message {
+        id: 2,
+        level: Error,
+        module: Init,
+        format: "init failed: {=Hsi|Pll|ClockSwitch|DmaEnable|Timer}",
+    };
+    pub const FAULT: Message = Message {
+        id: 3,
+        level: Error,
+        module: Watchdog,
+        format: "fault: {=NoTacho|Stall}, fan cut and leds blanked",
+    };
+    pub const DMA_ERROR: Message = Message {
+        id: 4,
+        level: Warn,
+        module: Dma,
+        format: "transfer errors {}, fifo errors {}, stream stopped",
+    };
+    // logged by idle once it sees the restart, the restart itself happens in the TIM3 interrupt
+    pub const DMA_RESTART: Message = Message {
+        id: 5,
+        level: Debug,
+        module: Dma,
+        format: "stream restarted {} times",
+    };
+    pub const DMA_TRANSFER: Message = Message {
+        id: 6,
+        level: Trace,
+        module: Dma,
+        format: "transfer complete, finished {x} active {x}",
+    };
+    pub const FRAMES: Message = Message {
+        id: 7,
+        level: Info,
+        module: Frames,
+        format: "frames submitted {} displayed {} repeated {} dropped {}, dma errors {}, render {} max {} cycles",
+    };
+    pub const FAN: Message = Message {
+        id: 8,
+        level: Info,
+        module: Fan,
+        format: "fan {=Off|Starting|Running|Stopping}",
+    };
+    pub const SPEED: Message = Message {
+        id: 9,
+        level: Info,
+        module: Fan,
+        format: "{} rpm, revolution {} cycles, tacho pulses missed {}",
+    };
+}
+
+pub const MESSAGES: [&Message; 10] = [
+    &msg::CLOCK_HSE,
+    &msg::CLOCK_HSI,
+    &msg::INIT_FAILED,
+    &msg::FAULT,
+    &msg::DMA_ERROR,
+    &msg::DMA_RESTART,
+    &msg::DMA_TRANSFER,
+    &msg::FRAMES,
+    &msg::FAN,
+    &msg::SPEED,
+];
+
+// Max. level per module, everything more verbose gets filtered out
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Filter {
+    pub levels: [Level; MODULES],
+}
+
+impl Filter {
+    pub const fn all(level: Level) -> Self {
+        Filter { levels: [level; MODULES] }
+    }
+
+    pub fn set(&mut self, module: Module, level: Level) {
+        self.levels[module as usize] = level;
+    }
+
+    pub fn enabled(&self, msg: &Message) -> bool {
+        msg.level <= self.levels[msg.module as usize]
+    }
+}
+
+pub const fn header(msg: &Message, args: usize) -> u32 {
+    SYNC << 24 | (args as u32) << 16 | msg.id as u32
+}
+
+// number of placeholders in a format
+pub fn arg_count(format: &str) -> usize {
+    format.matches('{').count()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Record {
+    pub id: u16,
+    pub timestamp: u32,
+    pub args: [u32; MAX_ARGS],
+    pub nargs: usize,
+}
+
+impl Record {
+    pub fn message(&self) -> &'static Message {
+        MESSAGES[self.id as usize]
+    }
+
+    pub fn args(&self) -> &[u32] {
+        &self.args[..self.nargs]
+    }
+}
+
+// Puts the records back together from the words of a log port
+#[derive(Default)]
+pub struct Decoder {
+    // the module of the port, its messages are the only valid ones
+    module: Option<Module>,
+    record: Option<Record>,
+    // words of the current record so far, the header included
+    words: usize,
+    // words skipped while looking for a header
+    skipped: u32,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Decoder::default()
+    }
+
+    // records of the port of module only
+    pub fn for_module(module: Module) -> Self {
+        Decoder {
+            module: Some(module),
+            ..Decoder::default()
+        }
+    }
+
+    // Feed the next word, returns a record once it's complete.
+    // Words that don't start a valid record are skipped until the next header.
+    pub fn feed(&mut self, word: u32) -> Option<Record> {
+        match self.record.as_mut() {
+            None => {
+                let id = (word & 0xFFFF) as usize;
+                let nargs = (word >> 16 & 0xFF) as usize;
+                let valid = word >> 24 == SYNC
+                    && id < MESSAGES.len()
+                    && nargs <= MAX_ARGS
+                    && nargs == arg_count(MESSAGES[id].format)
+                    && (self.module.is_none() || self.module == Some(MESSAGES[id].module));
+                if valid {
+                    self.record = Some(Record {
+                        id: id as u16,
+                        timestamp: 0,
+                        args: [0; MAX_ARGS],
+                        nargs,
+                    });
+                    self.words = 1;
+                } else {
+                    self.skipped += 1;
+                }
+                None
+            }
+            Some(record) => {
+                if self.words == 1 {
+                    record.timestamp = word;
+                } else {
+                    record.args[self.words - 2] = word;
+                }
+                self.words += 1;
+                if self.words == record.nargs + 2 {
+                    self.record.take()
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    pub fn skipped(&self) -> u32 {
+        self.skipped
+    }
+}
+
+// One decoder per log port, the words of different ports interleave on the wire
+pub struct PortDecoder {
+    decoders: [Decoder; MODULES],
+}
+
+impl Default for PortDecoder {
+    fn default() -> Self {
+        let mut decoders: [Decoder; MODULES] = Default::default();
+        for (decoder, &module) in decoders.iter_mut().zip(Module::ALL.iter()) {
+            decoder.module = Some(module);
+        }
+        PortDecoder { decoders }
+    }
+}
+
+impl PortDecoder {
+    pub fn new() -> Self {
+        PortDecoder::default()
+    }
+
+    // Feed an ITM packet, returns a record once it's complete. Other ports and sizes get ignored
+    pub fn feed(&mut self, packet: &Packet) -> Option<Record> {
+        let index = packet.port.checked_sub(FIRST_PORT)?;
+        if index >= MODULES || packet.size != 4 {
+            return None;
+        }
+        self.decoders[index].feed(packet.value)
+    }
+
+    // words skipped on all ports
+    pub fn skipped(&self) -> u32 {
+        self.decoders.iter().map(|decoder| decoder.skipped()).sum()
+    }
+}
+
+// The text of a record: module and level, then the format with the args filled in
+pub fn write_record<W: fmt::Write>(w: &mut W, record: &Record) -> fmt::Result {
+    let msg = record.message();
+    write!(w, "{:?} {:?}: ", msg.level, msg.module)?;
+    let mut args = record.args().iter();
+    let mut rest = msg.format;
+    while let Some(start) = rest.find('{') {
+        let end = start + rest[start..].find('}').unwrap_or(rest.len() - start);
+        w.write_str(&rest[..start])?;
+        let spec = &rest[start + 1..end];
+        let arg = *args.next().unwrap_or(&0);
+        if let Some(names) = spec.strip_prefix('=') {
+            match names.split('|').nth(arg as usize) {
+                Some(name) => w.write_str(name)?,
+                None => write!(w, "{}", arg)?,
+            }
+        } else if spec == "x" {
+            write!(w, "{:#x}", arg)?;
+        } else if spec == "i" {
+            write!(w, "{}", arg as i32)?;
+        } else {
+            write!(w, "{}", arg)?;
+        }
+        rest = rest.get(end + 1..).unwrap_or("");
+    }
+    w.write_str(rest)
+}
+
+// ITM packets as they come out of the trace port (e.g. the file openocd writes)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Packet {
+    pub port: usize,
+    pub value: u32,
+    pub size: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ParseState {
+    Header,
+    // payload bytes of a software source packet still to come
+    Payload { port: usize, size: usize, got: usize, value: u32 },
+    // bytes of a hardware source packet to skip
+    Skip(usize),
+    // timestamp or extension packets continue while bit 7 is set
+    Continuation,
+}
+
+// Splits the ITM byte stream into stimulus port packets, everything else
+// (sync, overflow, timestamps, DWT packets) gets skipped
+pub struct ItmParser {
+    state: ParseState,
+}
+
+impl Default for ItmParser {
+    fn default() -> Self {
+        ItmParser { state: ParseState::Header }
+    }
+}
+
+impl ItmParser {
+    pub fn new() -> Self {
+        ItmParser::default()
+    }
+
+    pub fn feed(&mut self, byte: u8) -> Option<Packet> {
+        match self.state {
+            ParseState::Header => {
+                let size = [0, 1, 2, 4][(byte & 0b11) as usize];
+                if size > 0 {
+                    if byte & 0b100 == 0 {
+                        self.state = ParseState::Payload {
+                            port: (byte >> 3) as usize,
+                            size,
+                            got: 0,
+                            value: 0,
+                        };
+                    } else {
+                        self.state = ParseState::Skip(size);
+                    }
+                } else if byte != 0 && byte != 0x80 && byte != 0x70 && byte & 0x80 != 0 {
+                    // sync is zeros and a final 0x80, 0x70 is an overflow, the rest are
+                    // timestamp and extension packets, continued while bit 7 is set
+                    self.state = ParseState::Continuation;
+                }
+                None
+            }
+            ParseState::Payload { port, size, got, value } => {
+                let value = value | (byte as u32) << (8 * got);
+                if got + 1 == size {
+                    self.state = ParseState::Header;
+                    Some(Packet { port, value, size })
+                } else {
+                    self.state = ParseState::Payload {
+                        port,
+                        size,
+                        got: got + 1,
+                        value,
+                    };
+                    None
+                }
+            }
+            ParseState::Skip(n) => {
+                self.state = if n > 1 { ParseState::Skip(n - 1) } else { ParseState::Header };
+                None
+            }
+            ParseState::Continuation => {
+                if byte & 0x80 == 0 {
+                    self.state = ParseState::Header;
+                }
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(record: &Record) -> String {
+        let mut s = String::new();
+        write_record(&mut s, record).unwrap();
+        s
+    }
+
+    // what the firmware writes for a record
+    fn words(msg: &Message, now: u32, args: &[u32]) -> Vec<u32> {
+        let mut words = vec![header(msg, args.len()), now];
+        words.extend_from_slice(args);
+        words
+    }
+
+    // the ITM packets of a word on a port
+    fn itm(port: usize, word: u32) -> Vec<u8> {
+        let mut bytes = vec![(port << 3) as u8 | 0b11];
+        bytes.extend_from_slice(&word.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn catalog_is_consistent() {
+        for (i, msg) in MESSAGES.iter().enumerate() {
+            assert_eq!(msg.id as usize, i);
+            assert!(arg_count(msg.format) <= MAX_ARGS);
+            assert_eq!(msg.format.matches('{').count(), msg.format.matches('}').count());
+        }
+    }
+
+    // the firmware logs these enums as their index, the names in the formats have to match
+    #[test]
+    fn enum_names_match() {
+        use crate::fan::FanState;
+        use crate::watchdog::Fault;
+        let name = |msg: &Message, arg: u32| {
+            let record = Record { id: msg.id, timestamp: 0, args: [arg; MAX_ARGS], nargs: 1 };
+            text(&record)
+        };
+        for &fault in &[Fault::NoTacho, Fault::Stall] {
+            assert!(name(&msg::FAULT, fault as u32).contains(&format!("fault: {:?},", fault)));
+        }
+        for &state in &[FanState::Off, FanState::Starting, FanState::Running, FanState::Stopping] {
+            assert!(name(&msg::FAN, state as u32).ends_with(&format!("fan {:?}", state)));
+        }
+    }
+
+    #[test]
+    fn filter_per_module() {
+        let mut filter = Filter::all(Level::Info);
+        assert!(filter.enabled(&msg::FRAMES));
+        assert!(filter.enabled(&msg::DMA_ERROR));
+        assert!(!filter.enabled(&msg::DMA_TRANSFER));
+        filter.set(Module::Dma, Level::Trace);
+        assert!(filter.enabled(&msg::DMA_TRANSFER));
+        filter.set(Module::Frames, Level::Warn);
+        assert!(!filter.enabled(&msg::FRAMES));
+        assert!(filter.enabled(&msg::INIT_FAILED));
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let mut decoder = Decoder::new();
+        let mut records = Vec::new();
+        for word in words(&msg::DMA_TRANSFER, 1234, &[0x2000_0000, 0x2000_1000])
+            .into_iter()
+            .chain(words(&msg::FAN, 99, &[2]))
+            .chain(words(&msg::DMA_RESTART, 5, &[2]))
+        {
+            records.extend(decoder.feed(word));
+        }
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].timestamp, 1234);
+        assert_eq!(text(&records[0]), "Trace Dma: transfer complete, finished 0x20000000 active 0x20001000");
+        assert_eq!(text(&records[1]), "Info Fan: fan Running");
+        assert_eq!(text(&records[2]), "Debug Dma: stream restarted 2 times");
+        assert_eq!(decoder.skipped(), 0);
+    }
+
+    #[test]
+    fn decoder_resyncs_after_lost_words() {
+        let mut decoder = Decoder::new();
+        let mut frames = words(&msg::FRAMES, 7, &[1, 2, 3, 4, 5, 6, 7]);
+        // the header got lost
+        frames.remove(0);
+        let mut records = Vec::new();
+        for word in frames.into_iter().chain(words(&msg::FAULT, 8, &[1])) {
+            records.extend(decoder.feed(word));
+        }
+        assert_eq!(records.len(), 1);
+        assert_eq!(text(&records[0]), "Error Watchdog: fault: Stall, fan cut and leds blanked");
+        assert_eq!(decoder.skipped(), 8);
+    }
+
+    #[test]
+    fn ports_get_decoded_separately() {
+        let mut parser = ItmParser::new();
+        let mut decoder = PortDecoder::new();
+        let fan = words(&msg::FAN, 1, &[2]);
+        let transfer = words(&msg::DMA_TRANSFER, 2, &[0x10, 0x20]);
+        // the records interleave word by word, a DMA record on the fan port is no valid record
+        let mut stream = Vec::new();
+        for i in 0..4 {
+            if let Some(&word) = transfer.get(i) {
+                stream.extend(itm(port(Module::Dma), word));
+            }
+            if let Some(&word) = fan.get(i) {
+                stream.extend(itm(port(Module::Fan), word));
+            }
+        }
+        stream.extend(itm(port(Module::Fan), transfer[0]));
+        // port 0 text and a port beyond the modules
+        stream.extend(itm(0, 0x0A21_6968));
+        stream.extend(itm(port(Module::Watchdog) + 1, fan[0]));
+        let records: Vec<Record> = stream
+            .into_iter()
+            .filter_map(|b| parser.feed(b))
+            .filter_map(|packet| decoder.feed(&packet))
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(text(&records[0]), "Info Fan: fan Running");
+        assert_eq!(text(&records[1]), "Trace Dma: transfer complete, finished 0x10 active 0x20");
+        assert_eq!(decoder.skipped(), 1);
+        assert_eq!(Module::ALL.iter().map(|&m| port(m)).collect::<Vec<_>>(), [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn unknown_enum_values_print_as_numbers() {
+        let record = Record {
+            id: msg::INIT_FAILED.id,
+            timestamp: 0,
+            args: [7; MAX_ARGS],
+            nargs: 1,
+        };
+        assert_eq!(text(&record), "Error Init: init failed: 7");
+    }
+
+    #[test]
+    fn itm_packets() {
+        let mut stream = vec![0, 0, 0, 0, 0, 0x80];
+        stream.extend(itm(0, u32::from_le_bytes(*b"hi! ")));
+        // local timestamp with a continuation byte, overflow and a 1 byte packet on port 0
+        stream.extend(&[0xC0, 0x81, 0x01, 0x70, 0x01, b'\n']);
+        // DWT hardware packet with 2 bytes
+        stream.extend(&[0x46, 0x12, 0x34]);
+        stream.extend(itm(FIRST_PORT, 0xDEAD_BEEF));
+        let mut parser = ItmParser::new();
+        let packets: Vec<Packet> = stream.into_iter().filter_map(|b| parser.feed(b)).collect();
+        assert_eq!(
+            packets,
+            [
+                Packet { port: 0, value: u32::from_le_bytes(*b"hi! "), size: 4 },
+                Packet { port: 0, value: b'\n' as u32, size: 1 },
+                Packet { port: FIRST_PORT, value: 0xDEAD_BEEF, size: 4 },
+            ]
+        );
+    }
+}
@@ -10,84 +10,167 @@ use panic_itm as _;
 use rtfm::app;
 
 use stm32ral::gpio;
-use stm32ral::{read_reg, write_reg};
-
-use heapless::{
-    consts::*,
-    i,
-    spsc::{Consumer, Producer, Queue},
-};
+use stm32ral::stm32f4::stm32f401::Interrupt;
+use stm32ral::{modify_reg, write_reg};
 
 use core::sync::atomic::Ordering;
 use core::sync::atomic::compiler_fence;
 
 #[macro_use]
 mod util;
+#[macro_use]
+mod itmlog;
 
 mod clocksetup;
 mod dmasetup;
 mod timersetup;
 mod spisetup;
+mod tachosetup;
+mod fansetup;
+
+use cortex_m::peripheral::DWT;
+use minipov::{font::Font, marquee::Marquee, tacho::Tacho, tlc59711::Tlc59711Command, DMAbuffer, COLS};
+use minipov::animation::{Animation, Duration, Frame, Mode, Player};
+use minipov::canvas::{Canvas, Dim, Half};
+use minipov::image::Image;
+use minipov::phase::{Phase, Rotation, Shifted};
+use minipov::pulses::PulseDivider;
+use minipov::fan::{Fan, FanConfig, FanState};
+use minipov::watchdog::{Fault, Watchdog};
+use util::InitError;
+use itmlog::Logger;
+use dmasetup::SpiStream;
+use minipov::dmaflags::Event;
+use minipov::framestats::FrameStats;
+use minipov::clocks::ClockSource;
+use minipov::log::msg;
+use minipov::triplebuffer::TripleBuffer;
+#[cfg(feature = "rgb")]
+use minipov::rgb::{ChannelOrder, Rgb, RgbCanvas, WhiteBalance};
+
+#[cfg(not(feature = "rgb"))]
+const MARQUEE_FONT: &Font = &minipov::font::FONT_8X12;
+// only 4 RGB pixel rows per driver
+#[cfg(feature = "rgb")]
+const MARQUEE_FONT: &Font = &minipov::font::FONT_5X7;
+
+// RGB mode: which led colour is wired to OUTR/OUTG/OUTB and the white balance of the leds
+#[cfg(feature = "rgb")]
+const CHANNEL_ORDER: ChannelOrder = ChannelOrder::Rgb;
+#[cfg(feature = "rgb")]
+const WHITE_BALANCE: WhiteBalance = WhiteBalance::NEUTRAL;
+
+// our fan generates 2 tacho pulses per revolution
+const PULSES_PER_REV: u32 = 2;
+
+// The pcb rotates with the fan, so it shuts down after a minute.
+// The soft start ramp keeps the start current of the fan low
+const FAN_CONFIG: FanConfig = FanConfig {
+    start_duty: 300,
+    ramp_ms: 2000,
+    run_ms: 60_000,
+    blank_ms: 200,
+};
 
-pub const COLS: usize = 128; //128
-pub const U16PERROW: usize = 14;
-pub const BUFLEN: usize = COLS * U16PERROW;
-
-#[repr(align(128))]
-pub struct DMAbuffer (
-    [u16; BUFLEN]
-);
-
-impl DMAbuffer {
-    //CMD 0x25, OUTTMG=0, EXTCLK=0,TMGRST=0, DSPRPT=1, BLANK=0, BC=1FFFFF
-    //const LEDCMD: [u16; 2] = [0x945F, 0xFFFF];
-    
-    //CMD 0x25, OUTTMG=0, EXTCLK=0,TMGRST=1, DSPRPT=0, BLANK=0, BC=1FFFFF
-    const LEDCMD: [u16; 2] = [0x949F, 0xFFFF];    
-        
-    //var gamma = new Uint16Array(256);
-    //for (var i=0.0;i<256;i=i+1.0) gamma[i]=Math.round(Math.pow(i/255.0,2.8)*65535);
-    //16 gamma corrected Brightness values 
-    const GAMMA: [u16; 16] = [0, 33, 232, 723, 1619, 3024, 5038, 7757,
-                              11274, 15678, 21058, 27499, 35085, 43899, 54023, 65535];
-
-    fn clear_col(self: &mut Self, col: usize) {
-        self.0[col * 14] = DMAbuffer::LEDCMD[0];
-        self.0[col * 14 + 1] = DMAbuffer::LEDCMD[1];
-        for i in 2..14 {
-            self.0[col * 14 + i] = DMAbuffer::GAMMA[0];
-        }
-    }
-    fn setpixel(self: &mut Self, col: usize, row: usize, val : usize) {
-        self.0[col * 14 + row + 2] = DMAbuffer::GAMMA[val];
-    }
-    fn set_col(self: &mut Self, col: usize) {
-        self.0[col * 14] = DMAbuffer::LEDCMD[0];
-        self.0[col * 14 + 1] = DMAbuffer::LEDCMD[1];
-        for i in 2..14 {
-            self.0[col * 14 + i] = DMAbuffer::GAMMA[15];
-        }
+// Tacho watchdog: time for the first edge after the fan got started
+// and the max. time between two edges once it turns
+const TACHO_START_MS: u32 = 3000;
+const TACHO_STALL_MS: u32 = 250;
+
+// Frame statistics go out over the ITM log this often
+const STATS_MS: u32 = 5000;
+
+// Half-turn mode, needs an even number of pulses per revolution
+#[derive(PartialEq)]
+#[allow(dead_code)]
+enum HalfTurns {
+    // one image around the whole cylinder
+    Off,
+    // an image on each half-turn with all COLS columns squeezed into it, every half-turn
+    // has its own image window, DMA transfer and buffer, so it shows the next frame
+    Same,
+    // one image per revolution, its two halves show different content
+    Different,
+}
+const HALF_TURNS: HalfTurns = HalfTurns::Off;
+// images started per revolution, the pulse divider lets only their tacho edges trigger TIM3
+const fn images_per_rev(half_turns: HalfTurns) -> u32 {
+    match half_turns {
+        HalfTurns::Off | HalfTurns::Different => 1,
+        HalfTurns::Same => 2,
     }
 }
+const IMAGES_PER_REV: u32 = images_per_rev(HALF_TURNS);
+const _: [(); 0 - !(PULSES_PER_REV % IMAGES_PER_REV == 0) as usize] = [];
+
+// What idle renders
+#[derive(PartialEq)]
+#[allow(dead_code)]
+enum Content {
+    // scrolling text, see MARQUEE_FONT
+    Marquee,
+    // the frames of ANIMATION
+    Animation,
+}
+const CONTENT: Content = Content::Marquee;
+
+// A light band bouncing up and down the cylinder, every image is a single column repeated all around
+const BAND_TOP: Image = Image { cols: 1, rows: 12, pixels: &[255, 255, 255, 255, 64, 0, 0, 0, 0, 0, 0, 0] };
+const BAND_MIDDLE: Image = Image { cols: 1, rows: 12, pixels: &[0, 0, 0, 64, 255, 255, 255, 255, 64, 0, 0, 0] };
+const BAND_BOTTOM: Image = Image { cols: 1, rows: 12, pixels: &[0, 0, 0, 0, 0, 0, 0, 64, 255, 255, 255, 255] };
+const BAND_FRAMES: [Frame; 3] = [
+    Frame { image: &BAND_TOP, duration: Duration::Revolutions(10) },
+    Frame { image: &BAND_MIDDLE, duration: Duration::Ms(100) },
+    Frame { image: &BAND_BOTTOM, duration: Duration::Revolutions(10) },
+];
+static ANIMATION: Animation = Animation { frames: &BAND_FRAMES, mode: Mode::PingPong };
+
+// Where the image starts on the cylinder, in 1/256 columns after the tacho edge
+const PHASE: Phase = Phase::from_cols(0);
+// Turn the image by this many 1/256 columns every revolution, 0 keeps it still
+// e.g. 64 turns it once in about 10 s at 50 revolutions per second
+const ROTATION: i32 = 0;
 
 #[app(device = stm32ral::stm32f4::stm32f401, peripherals = true)]
 const APP: () = {
     struct Resources {
         //Late Ressource
-        dmabufa: DMAbuffer,
-        dmabufb: DMAbuffer,
-        dmabufc: DMAbuffer,
         mygpiob: stm32ral::gpio::Instance,
-        myitm: cortex_m::peripheral::ITM,
-        mydma: stm32ral::dma::Instance,
-        dma_int_consumer: Consumer<'static, u32, U2,>,
-        idle_consumer: Consumer<'static, u32, U2,>,
-        dma_int_producer: Producer<'static, u32 , U2>,
-        idle_producer: Producer<'static, u32 , U2>,
+        // DMA1 stream 6 channel 2 (TIM4_UP) feeding SPI2
+        spidma: SpiStream,
+        // frames submitted, displayed and repeated and the DMA errors
+        framestats: FrameStats,
+        // the three DMA buffers, rendered in idle and sent by the DMA
+        buffers: TripleBuffer<DMAbuffer>,
+        // command words incl. global brightness, applied to every new buffer in dma_handler
+        ledcmd: Tlc59711Command,
+        myexti: stm32ral::exti::Instance,
+        // revolution speed measured from the tacho edges
+        tacho: Tacho,
+        // decides which tacho edges may start an image and counts the revolutions
+        pulses: PulseDivider,
+        // column timing, adapted to the fan speed after every image
+        mytim2: stm32ral::tim2::Instance,
+        mytim3: stm32ral::tim3::Instance,
+        // fan PWM
+        mytim1: stm32ral::tim1::Instance,
+        // blank the drivers directly on a fault
+        myspi: stm32ral::spi::Instance,
+        // image phase, the whole columns get applied when rendering, the fraction by the image window
+        rotation: Rotation,
+        // phase the buffers in DMA memory 0 and 1 were rendered with, set along with the targets.
+        // The fraction of the active one delays the next image window
+        target_phases: [Phase; 2],
+        // the binary ITM log, its ceiling stays below the tacho and TIM3 interrupts
+        logger: Logger,
     }
 
     #[init()]
     fn init(cx: init::Context) -> init::LateResources {
+        // The DMA buffers have to live in static memory since their addresses get passed around
+        // rtfm turns this into a &'static mut reference
+        static mut DMABUFS: [DMAbuffer; 3] = [DMAbuffer::new(), DMAbuffer::new(), DMAbuffer::new()];
+
         // Configure our clocks
         let myrcc = cx.device.RCC;
         let myflash = cx.device.FLASH;
@@ -96,97 +179,215 @@ const APP: () = {
         let mytim2 = cx.device.TIM2;
         let mytim3 = cx.device.TIM3;
         let mytim4 = cx.device.TIM4;
-        let myitm = cx.core.ITM;
-        let mydma = cx.device.DMA1;
+        let mytim1 = cx.device.TIM1;
+        let mut mydcb = cx.core.DCB;
+        let mut mydwt = cx.core.DWT;
+        let mut spidma = SpiStream::new(cx.device.DMA1, Interrupt::DMA1_STREAM6);
         let myspi = cx.device.SPI2;
-
-        // Configure our clocks
-        clocksetup::clocksetup(&myrcc, &myflash);
+        let mysyscfg = cx.device.SYSCFG;
+        let myexti = cx.device.EXTI;
+        let mut logger = Logger::new(cx.core.ITM);
+
+        // Start the cycle counter, our time base for animations and the tacho
+        // and for the timeouts of the setup
+        mydcb.enable_trace();
+        mydwt.enable_cycle_counter();
+
+        // Configure our clocks, same frequencies on the HSI if the crystal doesn't start
+        let clocksource = clocksetup::clocksetup(&myrcc, &myflash)
+            .unwrap_or_else(|err| init_failed(err, &mut logger, &myrcc, &mygpiob, &mut spidma));
+        if clocksource == ClockSource::Hse {
+            log!(&mut logger, msg::CLOCK_HSE);
+        } else {
+            log!(&mut logger, msg::CLOCK_HSI);
+        }
         //cortex_m::asm::bkpt();
         // Stop all timers on debug halt for better debugging
         timersetup::timer234debugstop(&mydbgmcu);
         // Setup GPIOB (LEDs and timer3 CC1 input)
         timersetup::portconfig(&myrcc, &mygpiob);
         // Setup timers
-        timersetup::timerconfig(&myrcc, &mytim2, &mytim3, &mytim4);
+        timersetup::timerconfig(&myrcc, &mytim2, &mytim3, &mytim4)
+            .unwrap_or_else(|err| init_failed(err, &mut logger, &myrcc, &mygpiob, &mut spidma));
+        // Fan PWM on PB0, still off
+        fansetup::fanconfig(&myrcc, &mytim1, &mygpiob);
         // Setup SPI
         spisetup::spiconfig(&myrcc, &myspi);
+        // Timestamp the tacho edges
+        tachosetup::tachoconfig(&myrcc, &mysyscfg, &myexti);
  
         // Setup dma
-        let mut dmabufa = DMAbuffer([0; BUFLEN]);
-        let mut dmabufb = DMAbuffer([0; BUFLEN]);
-        let mut dmabufc = DMAbuffer([0; BUFLEN]);
-        for i in 0..COLS/2 {
-            dmabufa.set_col(i);
-            dmabufb.set_col(i);
-            dmabufc.set_col(i);
-        } 
-        for i in 0..COLS/2 {
-            dmabufa.set_col(COLS/2+i);
-            dmabufb.set_col(COLS/2+i);
-            dmabufc.set_col(COLS/2+i);
-        } 
-
-        //for i in 0..14 {
-        //    write_reg!(stm32ral::spi, myspi, DR, dmabufa.0[i] as u32);
-        //    block_until! { read_reg!(stm32ral::spi, myspi, SR, TXE == Empty) }
-        //}
-
-        // Safety these raw pointers point to static initialized Memory buffers so they are always valid
-        // They get passed back and forth between idle and interrrupt handler 
-        // and only the code wich has the pointer can access the buffer.
-        let bufrefa = &mut dmabufa as *const _ as u32;
-        let bufrefb = &mut dmabufb as *const _ as u32;
-        let bufrefc = &mut dmabufc as *const _ as u32;
-        dmasetup::dmaconfig(&myrcc, &mydma, &myspi, bufrefa, bufrefb);
-
-        //Set up two SPSCs to pass around ownership of the DMA buffer pointers
-        //Safety: split call: in init interrupts are not yet enabled so mutating a static is still safe
-        static mut IDLE2DMA_SPSC: Queue<u32, U2> = Queue(i::Queue::new());
-        let (idle_producer, dma_int_consumer) = unsafe {IDLE2DMA_SPSC.split()};
-        static mut DMA2IDLE_SPSC: Queue<u32, U2> = Queue(i::Queue::new());
-        let (mut dma_int_producer, idle_consumer) = unsafe {DMA2IDLE_SPSC.split()};
-
-        //try to send the two buffers not owned by the dma  to idle 
-        // we've just taken one element out of the queue 
-        // so there should be a free slot to put the result into
-        if dma_int_producer.enqueue(bufrefc).is_err() {panic!("dma to idle queue full!")};
+        let dmabufs: &'static mut [DMAbuffer; 3] = DMABUFS;
+        for buf in dmabufs.iter_mut() {
+            for i in 0..COLS {
+                buf.set_col(i);
+            }
+        }
+
+        // Two buffers start out in the DMA, the third one is free for idle to render into.
+        // Only the DMA gets to see their addresses, idle gets a &mut to the buffer it renders.
+        let buffers = TripleBuffer::new(dmabufs);
+        let (bufa, bufb) = buffers.dma_targets();
+        dmasetup::dmaconfig(&myrcc, &mut spidma, &myspi, bufa, bufb)
+            .unwrap_or_else(|err| init_failed(err, &mut logger, &myrcc, &mygpiob, &mut spidma));
 
         //Return the now initialized Late Ressources
         init::LateResources {
-            myitm,
             mygpiob,
-            mydma,
-            idle_producer, 
-            dma_int_consumer,
-            dma_int_producer, 
-            idle_consumer,
-            dmabufa,
-            dmabufb,
-            dmabufc,
+            spidma,
+            framestats: FrameStats::new(),
+            buffers,
+            ledcmd: DMAbuffer::COMMAND,
+            myexti,
+            tacho: Tacho::new(clocksetup::SYSCLK_HZ, PULSES_PER_REV),
+            pulses: PulseDivider::new(PULSES_PER_REV, IMAGES_PER_REV),
+            mytim2,
+            mytim3,
+            mytim1,
+            myspi,
+            rotation: Rotation::new(PHASE, ROTATION),
+            // the initial buffers aren't shifted
+            target_phases: [Phase::default(); 2],
+            logger,
         }
     }
 
-    #[idle(resources = [idle_producer, idle_consumer])]
-    fn idle(cx: idle::Context) -> ! {
+    #[idle(resources = [buffers, ledcmd, tacho, pulses, rotation, mytim1, myspi, spidma, framestats, mygpiob, logger])]
+    fn idle(mut cx: idle::Context) -> ! {
+        // Global brightness of all colour groups (0..=127), lower it to save battery or dim indoors
+        // It takes effect with the next buffer handed to the DMA, no need to re-render
+        #[cfg(not(feature = "rgb"))]
+        cx.resources.ledcmd.lock(|ledcmd| ledcmd.set_brightness_all(Tlc59711Command::BC_MAX));
+        // In RGB mode the BC groups carry the white balance, scaled by the global brightness
+        #[cfg(feature = "rgb")]
+        cx.resources.ledcmd.lock(|ledcmd| WHITE_BALANCE.apply(ledcmd, CHANNEL_ORDER, Tlc59711Command::BC_MAX));
+        let mut marquees = [
+            Marquee::new(MARQUEE_FONT, "Hello from mini-pov!", 32, clocksetup::SYSCLK_HZ),
+            // the second half-turn in HalfTurns::Different mode
+            Marquee::new(MARQUEE_FONT, "mini-pov", -16, clocksetup::SYSCLK_HZ),
+        ];
+        let mut player = Player::new(&ANIMATION, clocksetup::SYSCLK_HZ);
+        let mut fan = Fan::new(clocksetup::SYSCLK_HZ, FAN_CONFIG);
+        let mut blank = None;
+        let mut watchdog = Watchdog::new(clocksetup::SYSCLK_HZ, TACHO_START_MS, TACHO_STALL_MS);
+        let mut dmaerrors = 0;
+        let mut restarts = 0;
+        let mut last_stats = DWT::get_cycle_count();
+        fan.start(DWT::get_cycle_count());
+        let mut fanstate = fan.state();
+        cx.resources.logger.lock(|logger| log!(logger, msg::FAN, fanstate));
         loop {
-            if let Some(next_buffer) = cx.resources.idle_consumer.dequeue() {
-                // Prepare the next Buffer
-
-
-
-
+            // No tacho edges while the fan should turn: the image triggers stopped
+            // and the drivers would keep the last column lit
+            let now = DWT::get_cycle_count();
+            let edges = cx.resources.tacho.lock(|tacho| tacho.edges());
+            if fan.state() == FanState::Off {
+                watchdog.disarm();
+            } else {
+                watchdog.arm(now, edges);
+            }
+            let new_fault = watchdog.fault().is_none();
+            if let Some(fault) = watchdog.check(now, edges) {
+                if new_fault {
+                    // Safe state: fan off, no more images and the drivers blanked
+                    fan.cut(now);
+                    fansetup::set_duty(cx.resources.mytim1, 0);
+                    // a stream that doesn't stop can't be helped, blank the drivers anyway
+                    cx.resources.spidma.lock(|spidma| dmasetup::dmastop(spidma)).ok();
+                    let mut cmd = cx.resources.ledcmd.lock(|ledcmd| *ledcmd);
+                    cmd.blank = true;
+                    spisetup::send_direct(cx.resources.myspi, &cmd);
+                    cx.resources.logger.lock(|logger| log!(logger, msg::FAULT, fault));
+                }
+                show_fault(&mut cx.resources.mygpiob, fault, now);
+                continue;
+            }
 
+            // Soft start and run timeout of the fan.
+            // The leds get blanked through the command words before the fan gets cut
+            fan.update(now);
+            if fan.state() != fanstate {
+                fanstate = fan.state();
+                cx.resources.logger.lock(|logger| log!(logger, msg::FAN, fanstate));
+            }
+            fansetup::set_duty(cx.resources.mytim1, fan.duty());
+            if blank != Some(fan.blank()) {
+                blank = Some(fan.blank());
+                cx.resources.ledcmd.lock(|ledcmd| ledcmd.blank = fan.blank());
+            }
+            // The DMA restarts on its own after an error, just report it and the restarts
+            // and dump the frame statistics and the fan speed every now and then
+            // the counters and the queued buffers at the same time, the DMA side updates them together
+            let framestats = &mut cx.resources.framestats;
+            let (stats, queued) = cx
+                .resources
+                .buffers
+                .lock(|buffers| framestats.lock(|stats| (*stats, buffers.queued())));
+            if stats.dma_errors() != dmaerrors {
+                dmaerrors = stats.dma_errors();
+                cx.resources
+                    .logger
+                    .lock(|logger| log!(logger, msg::DMA_ERROR, stats.dma.transfer_errors, stats.dma.fifo_errors));
+            }
+            let stream_restarts = cx.resources.spidma.lock(|spidma| spidma.restarts());
+            if stream_restarts != restarts {
+                restarts = stream_restarts;
+                cx.resources.logger.lock(|logger| log!(logger, msg::DMA_RESTART, restarts));
+            }
+            if now.wrapping_sub(last_stats) >= STATS_MS * (clocksetup::SYSCLK_HZ / 1000) {
+                last_stats = now;
+                let speed = cx.resources.tacho.lock(|tacho| tacho.speed());
+                cx.resources.logger.lock(|logger| {
+                    log!(
+                        logger,
+                        msg::FRAMES,
+                        stats.submitted,
+                        stats.displayed,
+                        stats.repeated,
+                        stats.dropped(queued),
+                        stats.dma_errors(),
+                        stats.render_cycles,
+                        stats.max_render_cycles
+                    );
+                    if let Some(speed) = speed {
+                        log!(logger, msg::SPEED, speed.rpm, speed.period, speed.missed);
+                    }
+                });
+            }
+            if let Some(mut guard) = cx.resources.buffers.lock(|buffers| buffers.acquire()) {
+                let render_start = DWT::get_cycle_count();
+                // Prepare the next Buffer
+                let revolutions = cx.resources.pulses.lock(|pulses| pulses.revolutions());
+                // the DMA won't touch it before we submit it below
+                let buf: &mut DMAbuffer = &mut guard;
+                let phase = cx.resources.rotation.lock(|rotation| rotation.phase());
+                #[cfg(not(feature = "rgb"))]
+                render(
+                    &mut Shifted::new(buf, phase.cols()),
+                    &mut marquees,
+                    &mut player,
+                    DWT::get_cycle_count(),
+                    revolutions,
+                    255,
+                );
+                #[cfg(feature = "rgb")]
+                render(
+                    &mut Shifted::new(&mut RgbCanvas::new(buf, CHANNEL_ORDER), phase.cols()),
+                    &mut marquees,
+                    &mut player,
+                    DWT::get_cycle_count(),
+                    revolutions,
+                    Rgb::new(255, 96, 0),
+                );
 
                 // compiler fence we *really make sure all other threads / cores / interupt handlers / DMAs <= we need this
                 // observe any changes made in the code until now.
                 // i.e. This prevents reordering load/stores accross this fence and compiles down to a dmb with memory clobber
                 compiler_fence(Ordering:: SeqCst);
-                // try to send it to DMA 
-                // there exist three buffers globally of which usually two but at least one one is always active 
-                // inside the DMA unit so max 2 Buffers can be queued at any time
-                // so there should always be a free slot in the queue to store the pointer
-                if cx.resources.idle_producer.enqueue(next_buffer).is_err() {panic!("idle to dma queue full!")};
+                // hand it to the DMA, it gets sent after the current buffer
+                cx.resources.buffers.lock(|buffers| buffers.submit(guard, phase));
+                let now = DWT::get_cycle_count();
+                cx.resources.framestats.lock(|stats| stats.submit(now, now.wrapping_sub(render_start)));
             // We dindn't get a buffer nothing to do but sleep
             } else {
                 //cortex_m::asm::wfi();
@@ -194,67 +395,154 @@ const APP: () = {
         }
     }
 
-    #[task(binds = DMA1_STREAM6, priority=3, resources = [myitm, mygpiob, mydma, dma_int_consumer, dma_int_producer])]
-    fn dma_handler(cx: dma_handler::Context) {
+    // Timestamp every tacho edge, highest priority to keep the jitter low
+    // The TIM3 trigger already fired (or not) on this edge, arm it only for the next edge that starts an image
+    // Advance the rotation for every revolution started, whatever the number of images per revolution
+    #[task(binds = EXTI4, priority=4, resources = [myexti, tacho, pulses, mytim3, rotation])]
+    fn tacho_handler(cx: tacho_handler::Context) {
+        let now = DWT::get_cycle_count();
+        write_reg!(stm32ral::exti, cx.resources.myexti, PR, PR4: 1);
+        let pulses = cx.resources.tacho.edge(now);
+        let revolutions = cx.resources.pulses.revolutions();
+        timersetup::arm_image_trigger(cx.resources.mytim3, cx.resources.pulses.edge(pulses));
+        for _ in 0..cx.resources.pulses.revolutions().wrapping_sub(revolutions) {
+            cx.resources.rotation.revolution();
+        }
+    }
+
+    // End of the image window, TIM3 stopped and gates TIM2 off until the next tacho edge.
+    // Stretch or shrink the columns so the image always spans the same angle,
+    // delay the window by the phase fraction of the buffer the DMA switched to with the last column.
+    // That's the memory the stream reads now, dma_handler might not have seen its transfer complete yet
+    // A DMA stream aborted by an error starts over here, in sync with the image again.
+    // No logging up here, idle reports the restarts
+    #[task(binds = TIM3, priority=4, resources = [mytim2, mytim3, tacho, spidma, target_phases])]
+    fn image_end_handler(cx: image_end_handler::Context) {
+        modify_reg!(stm32ral::tim3, cx.resources.mytim3, SR, UIF: 0);
+        if cx.resources.spidma.restart_pending() {
+            //a stream that doesn't stop stays pending, next image
+            cx.resources.spidma.restart().ok();
+        }
+        let phase = cx.resources.target_phases[cx.resources.spidma.active_memory()];
+        if let Some(period) = cx.resources.tacho.period().map(|p| p / IMAGES_PER_REV) {
+            let tim2period = timersetup::COLUMN_TIMING.counts_per_column(timersetup::timer_cycles(period));
+            timersetup::set_image_timing(cx.resources.mytim2, cx.resources.mytim3, tim2period, phase.delay(tim2period));
+        }
+    }
 
-        let (finished_buf,active_buf) = if read_reg!(stm32ral::dma, cx.resources.mydma, CR6, CT == Memory0) {
-            //Memory0 active, Memory 1 just finished
+    #[task(binds = DMA1_STREAM6, priority=3, resources = [mygpiob, spidma, framestats, buffers, ledcmd, target_phases, logger])]
+    fn dma_handler(mut cx: dma_handler::Context) {
+        // Tell transfer complete from the errors, a transfer error disables the stream,
+        // after a FIFO error words might be lost. Either way the image is out of sync:
+        // stop the stream and restart it at the end of the image window
+        let now = DWT::get_cycle_count();
+        let stats = cx.resources.framestats;
+        // While the stream waits for its restart the flags came from stopping it, nothing got sent
+        let event = cx.resources.spidma.lock(|spidma| {
+            let flags = spidma.flags();
+            spidma.clear_flags(flags);
+            let event = stats.dma_interrupt(now, flags, spidma.restart_pending());
+            if event == Event::Error {
+                spidma.abort().ok();
+            }
+            event
+        });
+        if event != Event::Complete {
+            return;
+        }
+
+        //the DMA just switched targets, the idle one is the buffer it finished
+        let (finished_buf, active_buf, memory) = cx.resources.spidma.lock(|spidma| {
+            let (finished, active) = spidma.switched();
+            (finished, active, spidma.active_memory())
+        });
+        if memory == 0 {
             write_reg!(gpio, cx.resources.mygpiob, BSRR, BS12: Set); //green on
-            (read_reg!(stm32ral::dma, cx.resources.mydma, M1AR6) , read_reg!(stm32ral::dma, cx.resources.mydma, M0AR6))
         } else {
-            //Memory1 active, Memory 0 just finished
             write_reg!(gpio, cx.resources.mygpiob, BSRR, BR12: Reset); //green off
-            (read_reg!(stm32ral::dma, cx.resources.mydma, M0AR6), read_reg!(stm32ral::dma, cx.resources.mydma, M1AR6))
-        };
+        }
 
-        // enqueue finished buffer as free buffer for idle task if it is not the same buffer 
-        // as the currently active one (was not re-scheduled)
+        // the finished buffer goes back to idle unless it was re-scheduled (it's the active one again)
         // might happen if the finished buffer was re scheduled due to no new data available
-        if finished_buf != active_buf {
-            if cx.resources.dma_int_producer.enqueue(finished_buf).is_err() {panic!("dma to idle queue full!")};
-        };
-
-        //Check for new data zu send
-        if let Some(next_buffer) = cx.resources.dma_int_consumer.dequeue() {
-            
-            if read_reg!(stm32ral::dma, cx.resources.mydma, CR6, CT == Memory0) {
-                //Memory 0 is active so update Memory 1 to next bufferr
-                write_reg!(stm32ral::dma, cx.resources.mydma, M1AR6, next_buffer);
-               
-            } else {
-                //Memory 1 is active so update Memory 0 to next bufferr
-                write_reg!(stm32ral::dma, cx.resources.mydma, M0AR6, next_buffer);
-            };    
+        let next = cx.resources.buffers.transfer_complete(finished_buf, active_buf);
+        // without a fresh frame the renderer was late and the active one gets repeated
+        stats.transfer(now, next.fresh.is_some());
+
+        // Patch the current command words (global brightness) into a freshly rendered buffer
+        // A re-scheduled buffer is the active one, we must not touch it while the DMA reads it
+        if let Some(buf) = next.fresh {
+            buf.set_commands(cx.resources.ledcmd);
+        }
 
-        } else {
-            // We dindn't get a new buffer re-schedule the currently active one instead
-            // This meaans we didn't get new data on time and as a result we re-transmit the currently active buffer
-            // In this case we only possess one pointer inside the DMA unit 
-            // and two pointers are somwhere in the queues or in use in the idle task
-            
-            if read_reg!(stm32ral::dma, cx.resources.mydma, CR6, CT == Memory0) {
-                //Memory 0 is active so update Memory 1 to active_buf (re-schedule)
-                write_reg!(stm32ral::dma, cx.resources.mydma, M1AR6, active_buf);
-               
-            } else {
-                //Memory 1 is active so update Memory 0 to to active_buf (re-schedule)
-                write_reg!(stm32ral::dma, cx.resources.mydma, M0AR6, active_buf);
-            };
-        };
-        //cortex_m::iprintln!(&mut cx.resources.myitm.stim[0], "I");
-        //Clear all DMA interupt Flags
-        write_reg!(
-            stm32ral::dma,
-            cx.resources.mydma,
-            HIFCR,
-            CDMEIF6: Clear,
-            CFEIF6: Clear,
-            CHTIF6: Clear,
-            CTCIF6: Clear,
-            CTEIF6: Clear
-        );
+        // If we dindn't get a new buffer the currently active one is re-scheduled instead
+        // This meaans we didn't get new data on time and as a result we re-transmit the currently active buffer
+        // In this case we only possess one pointer inside the DMA unit 
+        // and two pointers are somwhere in the queues or in use in the idle task
+        let target = next.target;
+        cx.resources.spidma.lock(|spidma| spidma.set_idle_target(target));
+        // its image window gets delayed by the fraction of the phase it got rendered with
+        let phase = cx.resources.buffers.phase_of(target);
+        cx.resources.target_phases.lock(|phases| phases[1 - memory] = phase);
+        log!(cx.resources.logger, msg::DMA_TRANSFER, finished_buf, active_buf);
     }
 };
 
+// Fault on the status leds: red PB2 on, green PB12 blinks fast without tacho and slowly for a stalled fan
+fn show_fault(gpiob: &mut impl rtfm::Mutex<T = stm32ral::gpio::Instance>, fault: Fault, now: u32) {
+    let blink_hz = match fault {
+        Fault::NoTacho => 4,
+        Fault::Stall => 1,
+    };
+    let on = now / (clocksetup::SYSCLK_HZ / 2 / blink_hz) % 2 == 0;
+    gpiob.lock(|gpiob| {
+        write_reg!(gpio, gpiob, BSRR, BS2: Set);
+        if on {
+            write_reg!(gpio, gpiob, BSRR, BS12: Set);
+        } else {
+            write_reg!(gpio, gpiob, BSRR, BR12: Reset);
+        }
+    });
+}
+
+// Setup failed in init: log it, stop the DMA, switch the fan off (PB0 high Z) and the red led (PB2) on.
+// There is no way to go on without working clocks, DMA or timers, so it stays there until a reset.
+fn init_failed(
+    err: InitError,
+    logger: &mut Logger,
+    rcc: &stm32ral::rcc::Instance,
+    gpiob: &stm32ral::gpio::Instance,
+    spidma: &mut SpiStream,
+) -> ! {
+    log!(logger, msg::INIT_FAILED, err);
+    dmasetup::dmastop(spidma).ok();
+    // takes PB0 back from the fan PWM
+    timersetup::portconfig(rcc, gpiob);
+    write_reg!(gpio, gpiob, BSRR, BS2: Set, BR12: Reset);
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
+
+// Draw the current frame of the animation, or the marquee around the cylinder or one per half-turn
+fn render<C: Canvas>(
+    canvas: &mut C,
+    marquees: &mut [Marquee; 2],
+    player: &mut Player,
+    now: u32,
+    revolutions: u32,
+    color: C::Color,
+) where
+    C::Color: Dim,
+{
+    if CONTENT == Content::Animation {
+        player.render(canvas, now, revolutions, color);
+    } else if HALF_TURNS == HalfTurns::Different {
+        marquees[0].render(&mut Half::new(canvas, 0), now, color);
+        marquees[1].render(&mut Half::new(canvas, 1), now, color);
+    } else {
+        marquees[0].render(canvas, now, color);
+    }
+}
+
 //Find out the type
 //let () = cx.device.GPIOB;
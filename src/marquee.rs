@@ -0,0 +1,168 @@
+// Scrolling text around the cylinder.
+// The scroll position advances with the elapsed time, not per frame,
+// so the speed stays the same when a frame gets repeated or the fan speed changes.
+
+use crate::canvas::Canvas;
+use crate::dmabuffer::COLS;
+use crate::font::{draw_glyph_col, Font};
+
+// empty columns between the end of the message and its next start
+pub const GAP: usize = 8;
+
+pub struct Marquee {
+    font: &'static Font,
+    text: &'static str,
+    // columns per second, the sign selects the direction
+    speed: i32,
+    // frequency of the timestamps passed to render
+    tick_hz: u32,
+    // scroll position in 1/tick_hz columns, kept within one period
+    position: i64,
+    // width of the canvas it scrolls around
+    cols: usize,
+    last_tick: Option<u32>,
+}
+
+impl Marquee {
+    pub fn new(font: &'static Font, text: &'static str, speed: i32, tick_hz: u32) -> Self {
+        Marquee {
+            font,
+            text,
+            speed,
+            tick_hz,
+            position: 0,
+            cols: COLS,
+            last_tick: None,
+        }
+    }
+
+    pub fn set_text(&mut self, text: &'static str) {
+        self.text = text;
+        self.position = 0;
+    }
+
+    pub fn set_speed(&mut self, speed: i32) {
+        self.speed = speed;
+    }
+
+    // Length of the scrolled strip.
+    // Messages shorter than the canvas (the circumference) scroll seamlessly around the cylinder,
+    // longer ones are padded with GAP empty columns.
+    pub fn period(&self) -> usize {
+        let len = self.font.text_width(self.text) + GAP;
+        if len < self.cols { self.cols } else { len }
+    }
+
+    // current scroll offset in whole columns
+    pub fn offset(&self) -> usize {
+        (self.position / self.tick_hz as i64) as usize
+    }
+
+    // advance the scroll position to the timestamp now (wrapping tick counter)
+    pub fn advance(&mut self, now: u32) {
+        if let Some(last) = self.last_tick {
+            let elapsed = now.wrapping_sub(last) as i64;
+            let period = self.period() as i64 * self.tick_hz as i64;
+            self.position = (self.position + elapsed * self.speed as i64).rem_euclid(period);
+        }
+        self.last_tick = Some(now);
+    }
+
+    // Fill a whole canvas with the scroll position at timestamp now.
+    // Strip column s shows up at buffer column (s - offset) mod period,
+    // so anything leaving the last column comes back in at column 0 and vice versa.
+    pub fn render<C: Canvas>(&mut self, canvas: &mut C, now: u32, color: C::Color) {
+        self.cols = canvas.cols();
+        self.advance(now);
+        let period = self.period();
+        let offset = self.offset();
+        for col in 0..self.cols {
+            canvas.clear_col(col);
+        }
+        let mut s = 0;
+        for c in self.text.chars() {
+            for &bits in self.font.glyph(c) {
+                let col = (s + period - offset) % period;
+                if col < self.cols {
+                    draw_glyph_col(canvas, self.font, col, bits, color);
+                }
+                s += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dmabuffer::{DMAbuffer, ROWS};
+    use crate::font::FONT_8X12;
+
+    const HZ: u32 = 1000;
+
+    fn col_bits(buf: &DMAbuffer, col: usize) -> u16 {
+        let top = FONT_8X12.top_row(ROWS);
+        (0..FONT_8X12.height)
+            .filter(|&row| buf.pwm(col, top + row) != 0)
+            .fold(0, |bits, row| bits | 1 << row)
+    }
+
+    #[test]
+    fn speed_is_independent_of_frame_rate() {
+        let mut slow = Marquee::new(&FONT_8X12, "HI", 20, HZ);
+        let mut fast = Marquee::new(&FONT_8X12, "HI", 20, HZ);
+        slow.advance(0);
+        fast.advance(0);
+        slow.advance(1000);
+        for t in (10..=1000).step_by(10) {
+            fast.advance(t);
+        }
+        assert_eq!(slow.offset(), 20);
+        assert_eq!(fast.offset(), 20);
+    }
+
+    #[test]
+    fn tick_counter_wraparound() {
+        let mut m = Marquee::new(&FONT_8X12, "HI", 10, HZ);
+        m.advance(u32::MAX - 499);
+        m.advance(500);
+        assert_eq!(m.offset(), 10);
+    }
+
+    #[test]
+    fn negative_speed_scrolls_backwards() {
+        let mut m = Marquee::new(&FONT_8X12, "HI", -3, HZ);
+        m.advance(0);
+        m.advance(1000);
+        assert_eq!(m.offset(), COLS - 3);
+    }
+
+    #[test]
+    fn text_wraps_across_the_seam() {
+        let mut m = Marquee::new(&FONT_8X12, "MW", 4, HZ);
+        let mut buf = DMAbuffer::new();
+        m.render(&mut buf, 0, 255);
+        // the M starts at column 0
+        for (i, &bits) in FONT_8X12.glyph('M').iter().enumerate() {
+            assert_eq!(col_bits(&buf, i), bits);
+        }
+        // one second later it straddles column COLS-1 and column 0
+        m.render(&mut buf, 1000, 255);
+        for (i, &bits) in FONT_8X12.glyph('M').iter().enumerate() {
+            assert_eq!(col_bits(&buf, (i + COLS - 4) % COLS), bits);
+        }
+        for (i, &bits) in FONT_8X12.glyph('W').iter().enumerate() {
+            assert_eq!(col_bits(&buf, 4 + i), bits);
+        }
+    }
+
+    #[test]
+    fn long_text_uses_its_own_period() {
+        let text = "a message that is longer than the cylinder";
+        let mut m = Marquee::new(&FONT_8X12, text, 1, HZ);
+        assert_eq!(m.period(), FONT_8X12.text_width(text) + GAP);
+        let mut buf = DMAbuffer::new();
+        m.render(&mut buf, 0, 255);
+        assert_eq!(col_bits(&buf, 0), FONT_8X12.glyph('a')[0]);
+    }
+}
@@ -0,0 +1,148 @@
+// Image phase: how far the image is turned on the cylinder, relative to the tacho edge.
+// It's counted in 1/SUBCOLS columns. The whole columns are applied when rendering
+// (the image gets drawn shifted into the buffer), the fraction by delaying the image window.
+
+use crate::canvas::Canvas;
+use crate::dmabuffer::COLS;
+
+pub const SUBCOLS: u32 = 256;
+// a full turn of the image
+pub const TURN: u32 = COLS as u32 * SUBCOLS;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Phase(u32);
+
+impl Phase {
+    pub const fn new(subcols: u32) -> Self {
+        Phase(subcols % TURN)
+    }
+
+    pub const fn from_cols(cols: usize) -> Self {
+        Phase::new((cols % COLS) as u32 * SUBCOLS)
+    }
+
+    // turn further by delta sub columns, negative turns back
+    pub fn turn(self, delta: i32) -> Self {
+        Phase((self.0 as i64 + delta as i64).rem_euclid(TURN as i64) as u32)
+    }
+
+    pub fn subcols(self) -> u32 {
+        self.0
+    }
+
+    // whole columns, applied when rendering
+    pub fn cols(self) -> usize {
+        (self.0 / SUBCOLS) as usize
+    }
+
+    // delay of the image window for the fraction, in steps of a column made of counts_per_column
+    pub fn delay(self, counts_per_column: u32) -> u32 {
+        (self.0 % SUBCOLS) * counts_per_column / SUBCOLS
+    }
+}
+
+// Continuous rotation: the phase advances by speed sub columns every revolution
+pub struct Rotation {
+    phase: Phase,
+    speed: i32,
+}
+
+impl Rotation {
+    pub const fn new(phase: Phase, speed: i32) -> Self {
+        Rotation { phase, speed }
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    pub fn set_phase(&mut self, phase: Phase) {
+        self.phase = phase;
+    }
+
+    // speed 0 keeps the image still
+    pub fn set_speed(&mut self, speed: i32) {
+        self.speed = speed;
+    }
+
+    // call once per revolution
+    pub fn revolution(&mut self) -> Phase {
+        self.phase = self.phase.turn(self.speed);
+        self.phase
+    }
+}
+
+// A canvas drawn cols columns further, wrapping around the cylinder
+pub struct Shifted<'a, C: Canvas> {
+    canvas: &'a mut C,
+    cols: usize,
+}
+
+impl<'a, C: Canvas> Shifted<'a, C> {
+    pub fn new(canvas: &'a mut C, cols: usize) -> Self {
+        Shifted { canvas, cols: cols % COLS }
+    }
+}
+
+impl<'a, C: Canvas> Canvas for Shifted<'a, C> {
+    type Color = C::Color;
+    const OFF: C::Color = C::OFF;
+
+    fn rows(&self) -> usize {
+        self.canvas.rows()
+    }
+
+    fn put_pixel(&mut self, col: usize, row: usize, color: C::Color) {
+        self.canvas.put_pixel((col + self.cols) % COLS, row, color);
+    }
+
+    fn clear_col(&mut self, col: usize) {
+        self.canvas.clear_col((col + self.cols) % COLS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dmabuffer::DMAbuffer;
+
+    #[test]
+    fn phase_wraps_around_the_cylinder() {
+        assert_eq!(Phase::new(TURN + 5).subcols(), 5);
+        assert_eq!(Phase::new(0).turn(-1).subcols(), TURN - 1);
+        assert_eq!(Phase::from_cols(COLS + 3).cols(), 3);
+        assert_eq!(Phase::new(TURN - 1).turn(2).subcols(), 1);
+    }
+
+    #[test]
+    fn fraction_becomes_a_window_delay() {
+        let phase = Phase::new(5 * SUBCOLS + SUBCOLS / 4);
+        assert_eq!(phase.cols(), 5);
+        assert_eq!(phase.delay(16), 4);
+        assert_eq!(phase.delay(25), 6);
+        assert_eq!(Phase::from_cols(7).delay(25), 0);
+    }
+
+    #[test]
+    fn rotation_advances_every_revolution() {
+        let mut rotation = Rotation::new(Phase::new(0), SUBCOLS as i32 / 4);
+        for _ in 0..4 {
+            rotation.revolution();
+        }
+        assert_eq!(rotation.phase(), Phase::from_cols(1));
+        rotation.set_speed(-(SUBCOLS as i32));
+        assert_eq!(rotation.revolution(), Phase::new(0));
+        assert_eq!(rotation.revolution(), Phase::from_cols(COLS - 1));
+    }
+
+    #[test]
+    fn shifted_canvas_wraps_columns() {
+        let mut buf = DMAbuffer::new();
+        let mut canvas = Shifted::new(&mut buf, 3);
+        canvas.put_pixel(COLS - 1, 0, 255);
+        canvas.put_pixel(0, 1, 255);
+        assert_eq!(buf.pwm(2, 0), 0xFFFF);
+        assert_eq!(buf.pwm(3, 1), 0xFFFF);
+        assert_eq!(buf.pwm(COLS - 1, 0), 0);
+    }
+}
@@ -0,0 +1,95 @@
+// Which tacho edges start an image.
+// The fan gives pulses_per_rev pulses per revolution, but only every pulses_per_image-th edge
+// may trigger the image window, otherwise we get partial images or one that flips by half a turn.
+// The TIM3 trigger gets armed right before these edges and disarmed before all the others.
+// It also counts the revolutions, pulse 0 starts one.
+
+pub struct PulseDivider {
+    pulses_per_rev: u32,
+    pulses_per_image: u32,
+    // pulse index within the revolution, pulse 0 starts the first image
+    pulse: u32,
+    // revolutions started so far, wrapping
+    revolutions: u32,
+}
+
+impl PulseDivider {
+    // images_per_rev has to divide pulses_per_rev, 2 draws an image on each half-turn
+    pub const fn new(pulses_per_rev: u32, images_per_rev: u32) -> Self {
+        PulseDivider {
+            pulses_per_rev,
+            pulses_per_image: pulses_per_rev / images_per_rev,
+            // the very first edge is pulse 0
+            pulse: pulses_per_rev - 1,
+            revolutions: 0,
+        }
+    }
+
+    // count the pulses of a tacho edge (see Tacho::edge), true if the next edge starts an image
+    pub fn edge(&mut self, pulses: u32) -> bool {
+        let pulse = self.pulse + pulses;
+        self.revolutions = self.revolutions.wrapping_add(pulse / self.pulses_per_rev);
+        self.pulse = pulse % self.pulses_per_rev;
+        self.armed()
+    }
+
+    pub fn armed(&self) -> bool {
+        self.pulse % self.pulses_per_image == self.pulses_per_image - 1
+    }
+
+    pub fn pulse(&self) -> u32 {
+        self.pulse
+    }
+
+    pub fn revolutions(&self) -> u32 {
+        self.revolutions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_image_per_revolution() {
+        let mut div = PulseDivider::new(2, 1);
+        // armed for the first edge
+        assert!(div.armed());
+        let armed: [bool; 6] = [div.edge(1), div.edge(1), div.edge(1), div.edge(1), div.edge(1), div.edge(1)];
+        assert_eq!(armed, [false, true, false, true, false, true]);
+    }
+
+    #[test]
+    fn image_per_half_turn() {
+        let mut div = PulseDivider::new(2, 2);
+        assert!((0..4).all(|_| div.edge(1)));
+        let mut div = PulseDivider::new(4, 2);
+        let armed: [bool; 4] = [div.edge(1), div.edge(1), div.edge(1), div.edge(1)];
+        assert_eq!(armed, [false, true, false, true]);
+    }
+
+    #[test]
+    fn stays_in_sync_over_missing_pulses_and_glitches() {
+        let mut div = PulseDivider::new(2, 1);
+        div.edge(1);
+        // a glitch doesn't count
+        assert!(!div.edge(0));
+        // pulse 1 went missing, this edge is pulse 0 again
+        assert!(!div.edge(2));
+        assert_eq!(div.pulse(), 0);
+        assert!(div.edge(1));
+    }
+
+    #[test]
+    fn counts_revolutions_not_images() {
+        let mut div = PulseDivider::new(2, 2);
+        for _ in 0..6 {
+            div.edge(1);
+        }
+        assert_eq!(div.revolutions(), 3);
+        // a missing pulse 0 still starts a revolution, a glitch doesn't
+        div.edge(2);
+        div.edge(0);
+        assert_eq!((div.revolutions(), div.pulse()), (4, 1));
+    }
+}
@@ -0,0 +1,215 @@
+// RGB mode: the TLC59711 is made of 4 RGB channel groups (OUTR0/G0/B0 ... OUTR3/G3/B3),
+// so every driver lights 4 RGB pixels per column, pixel row n of a driver uses OUTRn/Gn/Bn.
+// The BC global brightness groups then act as white balance.
+
+use crate::canvas::{Canvas, Dim};
+use crate::dmabuffer::{DMAbuffer, DRIVERS};
+use crate::gamma::CURVE_RGB;
+use crate::tlc59711::{ColorGroup, Tlc59711Command};
+
+pub const PIXELS_PER_DRIVER: usize = 4;
+pub const RGB_ROWS: usize = DRIVERS * PIXELS_PER_DRIVER;
+
+const RED: usize = 0;
+const GREEN: usize = 1;
+const BLUE: usize = 2;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const BLACK: Rgb = Rgb::new(0, 0, 0);
+    pub const WHITE: Rgb = Rgb::new(255, 255, 255);
+    pub const RED: Rgb = Rgb::new(255, 0, 0);
+    pub const GREEN: Rgb = Rgb::new(0, 255, 0);
+    pub const BLUE: Rgb = Rgb::new(0, 0, 255);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Rgb { r, g, b }
+    }
+
+    fn channels(self) -> [u8; 3] {
+        [self.r, self.g, self.b]
+    }
+}
+
+impl Dim for Rgb {
+    fn dim(self, brightness: u8) -> Rgb {
+        Rgb::new(self.r.dim(brightness), self.g.dim(brightness), self.b.dim(brightness))
+    }
+}
+
+// Which led colour is wired to the OUTR, OUTG and OUTB outputs of a channel group,
+// e.g. Grb means green on OUTR, red on OUTG and blue on OUTB.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChannelOrder {
+    Rgb,
+    Rbg,
+    Grb,
+    Gbr,
+    Brg,
+    Bgr,
+}
+
+impl ChannelOrder {
+    // colour on OUTR, OUTG and OUTB
+    fn colors(self) -> [usize; 3] {
+        match self {
+            ChannelOrder::Rgb => [RED, GREEN, BLUE],
+            ChannelOrder::Rbg => [RED, BLUE, GREEN],
+            ChannelOrder::Grb => [GREEN, RED, BLUE],
+            ChannelOrder::Gbr => [GREEN, BLUE, RED],
+            ChannelOrder::Brg => [BLUE, RED, GREEN],
+            ChannelOrder::Bgr => [BLUE, GREEN, RED],
+        }
+    }
+}
+
+const GROUPS: [ColorGroup; 3] = [ColorGroup::Red, ColorGroup::Green, ColorGroup::Blue];
+
+// Per colour BC values (0..=127) which make full white look white.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WhiteBalance {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl WhiteBalance {
+    pub const NEUTRAL: WhiteBalance = WhiteBalance {
+        r: Tlc59711Command::BC_MAX,
+        g: Tlc59711Command::BC_MAX,
+        b: Tlc59711Command::BC_MAX,
+    };
+
+    // Write the white balance, scaled by a global brightness (0..=127),
+    // into the BC groups the colours are wired to.
+    pub fn apply(&self, cmd: &mut Tlc59711Command, order: ChannelOrder, brightness: u8) {
+        let max = Tlc59711Command::BC_MAX as u16;
+        let brightness = brightness.min(Tlc59711Command::BC_MAX) as u16;
+        let balance = [self.r, self.g, self.b];
+        for (group, &color) in GROUPS.iter().zip(order.colors().iter()) {
+            let bc = balance[color].min(Tlc59711Command::BC_MAX) as u16;
+            cmd.set_brightness(*group, ((bc * brightness + max / 2) / max) as u8);
+        }
+    }
+}
+
+// A DMAbuffer seen as RGB_ROWS rows of RGB pixels.
+pub struct RgbCanvas<'a> {
+    buf: &'a mut DMAbuffer,
+    order: ChannelOrder,
+}
+
+impl<'a> RgbCanvas<'a> {
+    pub fn new(buf: &'a mut DMAbuffer, order: ChannelOrder) -> Self {
+        RgbCanvas { buf, order }
+    }
+}
+
+impl<'a> Canvas for RgbCanvas<'a> {
+    type Color = Rgb;
+    const OFF: Rgb = Rgb::BLACK;
+
+    fn rows(&self) -> usize {
+        RGB_ROWS
+    }
+
+    // every colour goes through its own gamma curve
+    fn put_pixel(&mut self, col: usize, row: usize, color: Rgb) {
+        let driver = row / PIXELS_PER_DRIVER;
+        // the grayscale data starts with OUTB3, OUTG3, OUTR3, OUTB2 ...
+        let group = 3 * (PIXELS_PER_DRIVER - 1 - row % PIXELS_PER_DRIVER);
+        let channels = color.channels();
+        for (output, &c) in self.order.colors().iter().enumerate() {
+            // OUTR is the last of the three
+            let pwm = CURVE_RGB[c][channels[c] as usize];
+            self.buf.set_pwm(col, driver, group + 2 - output, pwm);
+        }
+    }
+
+    fn clear_col(&mut self, col: usize) {
+        self.buf.clear_col(col);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dmabuffer::{ROWS_PER_DRIVER, U16PERDRIVER, U16PERROW};
+    use crate::font::{draw_text, FONT_5X7};
+
+    // grayscale data of the driver next to the mcu in column col
+    fn outputs(buf: &DMAbuffer, col: usize) -> &[u16] {
+        let start = (col + 1) * U16PERROW - ROWS_PER_DRIVER;
+        &buf.0[start..start + ROWS_PER_DRIVER]
+    }
+
+    #[test]
+    fn pixels_map_to_channel_groups() {
+        let mut buf = DMAbuffer::new();
+        let mut canvas = RgbCanvas::new(&mut buf, ChannelOrder::Rgb);
+        canvas.put_pixel(2, 0, Rgb::new(255, 0, 0));
+        canvas.put_pixel(2, 3, Rgb::new(0, 0, 255));
+        // OUTR0 is the last word, OUTB3 the first
+        let out = outputs(&buf, 2);
+        assert_eq!(out[11], 0xFFFF);
+        assert_eq!(out[0], 0xFFFF);
+        assert_eq!(out.iter().filter(|&&v| v != 0).count(), 2);
+    }
+
+    #[test]
+    fn channel_order_swaps_outputs() {
+        let mut buf = DMAbuffer::new();
+        let mut canvas = RgbCanvas::new(&mut buf, ChannelOrder::Grb);
+        canvas.put_pixel(0, 0, Rgb::GREEN);
+        // green is wired to OUTR0
+        assert_eq!(outputs(&buf, 0)[11], 0xFFFF);
+        assert_eq!(outputs(&buf, 0)[10], 0);
+        let mut canvas = RgbCanvas::new(&mut buf, ChannelOrder::Bgr);
+        canvas.put_pixel(0, 1, Rgb::BLUE);
+        // blue on OUTR1
+        assert_eq!(outputs(&buf, 0)[8], 0xFFFF);
+    }
+
+    #[test]
+    fn per_colour_gamma() {
+        let mut buf = DMAbuffer::new();
+        let mut canvas = RgbCanvas::new(&mut buf, ChannelOrder::Rgb);
+        canvas.put_pixel(0, 0, Rgb::new(10, 100, 200));
+        let out = outputs(&buf, 0);
+        assert_eq!(out[11], CURVE_RGB[RED][10]);
+        assert_eq!(out[10], CURVE_RGB[GREEN][100]);
+        assert_eq!(out[9], CURVE_RGB[BLUE][200]);
+    }
+
+    #[test]
+    fn white_balance_follows_the_wiring() {
+        let wb = WhiteBalance { r: 127, g: 100, b: 50 };
+        let mut cmd = Tlc59711Command::DEFAULT;
+        wb.apply(&mut cmd, ChannelOrder::Rgb, 127);
+        assert_eq!((cmd.bcr, cmd.bcg, cmd.bcb), (127, 100, 50));
+        wb.apply(&mut cmd, ChannelOrder::Brg, 127);
+        assert_eq!((cmd.bcr, cmd.bcg, cmd.bcb), (50, 127, 100));
+        // global brightness scales all groups
+        wb.apply(&mut cmd, ChannelOrder::Rgb, 64);
+        assert_eq!((cmd.bcr, cmd.bcg, cmd.bcb), (64, 50, 25));
+        WhiteBalance::NEUTRAL.apply(&mut cmd, ChannelOrder::Rgb, 255);
+        assert_eq!(cmd, Tlc59711Command::DEFAULT);
+    }
+
+    #[test]
+    fn text_in_colour() {
+        let mut buf = DMAbuffer::new();
+        let mut canvas = RgbCanvas::new(&mut buf, ChannelOrder::Rgb);
+        // the font is taller than the canvas and gets clipped
+        assert_eq!(draw_text(&mut canvas, &FONT_5X7, 0, "|", Rgb::RED), 5);
+        let lit = buf.0.chunks(U16PERDRIVER).flat_map(|d| d[2..].iter()).filter(|&&v| v != 0).count();
+        // the 6 pixel bar of the '|' lights one red output per pixel row
+        assert_eq!(lit, RGB_ROWS.min(6));
+    }
+}
@@ -1,4 +1,14 @@
-use stm32ral::{modify_reg, write_reg};
+use stm32ral::{modify_reg, read_reg, write_reg};
+
+use minipov::{dmabuffer::U16PERDRIVER, tlc59711::Tlc59711Command, U16PERROW};
+
+use crate::clocksetup::FREQUENCIES;
+
+// SPI2 sits on APB1, divide its clock by 8 (5.25 MBit at 42 MHz)
+pub const SPIDIV: u32 = 8;
+pub const SPI_HZ: u32 = FREQUENCIES.pclk1 / SPIDIV;
+// BR: 2 -> 0, 4 -> 1 ... 256 -> 7
+const _: [(); 0 - !(SPIDIV.is_power_of_two() & (SPIDIV >= 2) & (SPIDIV <= 256)) as usize] = [];
 
 pub fn spiconfig(
     rcc: &stm32ral::rcc::Instance,
@@ -24,15 +34,14 @@ pub fn spiconfig(
     );
 
     // we only need tx on first rising edge data is already stable and latched
-    // since APB1 is already half the clock speed compared to AHB1 
-    // we only divide by 8 and not by 16 to get 6MBit SPI Line speed
+    // APB1 divided by SPIDIV gives the SPI Line speed
     write_reg!(
         stm32ral::spi,
         spi,
         CR1,
         BIDIMODE: Unidirectional,
         BIDIOE: OutputEnabled,
-        BR: Div8,
+        BR: SPIDIV.trailing_zeros() - 1,
         CPHA: FirstEdge,
         CPOL: IdleLow,
         CRCEN: Disabled,
@@ -46,3 +55,20 @@ pub fn spiconfig(
         SSM: Enabled
     ); 
 }
+
+// Send cmd with all outputs off to every driver, bypassing the DMA.
+// Used to blank the leds when the image pipeline is dead, the DMA stream must be stopped.
+pub fn send_direct(spi: &stm32ral::spi::Instance, cmd: &Tlc59711Command) {
+    let words = cmd.words();
+    for i in 0..U16PERROW {
+        let word = match i % U16PERDRIVER {
+            0 => words[0],
+            1 => words[1],
+            _ => 0,
+        };
+        block_until! { read_reg!(stm32ral::spi, spi, SR, TXE == Empty) }
+        write_reg!(stm32ral::spi, spi, DR, word as u32);
+    }
+    //the drivers latch once the clock stays idle
+    block_while! { read_reg!(stm32ral::spi, spi, SR, BSY == Busy) }
+}
@@ -0,0 +1,272 @@
+// Revolution speed from the tacho edges.
+// Every edge gets timestamped with a free running tick counter (the DWT cycle counter),
+// the intervals are filtered and checked for glitches and missing pulses.
+
+// weight of a new interval in the moving average is 1/2^FILTER_SHIFT
+const FILTER_SHIFT: u32 = 3;
+// accept a speed change after this many intervals in a row which look like glitches
+// or like missing pulses
+const RESYNC: u32 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Speed {
+    // filtered revolution period in ticks
+    pub period: u32,
+    pub rpm: u32,
+    // pulses missed since start
+    pub missed: u32,
+}
+
+pub struct Tacho {
+    tick_hz: u32,
+    pulses_per_rev: u32,
+    // last accepted edge and the very last edge, glitch or not
+    last_edge: Option<u32>,
+    last_raw: u32,
+    // filtered interval between two pulses in ticks
+    interval: Option<u32>,
+    missed: u32,
+    rejected: u32,
+    // long intervals in a row and the pulses they counted as missing
+    stretched: u32,
+    stretched_missed: u32,
+    // every edge, glitch or not, wrapping
+    edges: u32,
+}
+
+impl Tacho {
+    pub const fn new(tick_hz: u32, pulses_per_rev: u32) -> Self {
+        Tacho {
+            tick_hz,
+            pulses_per_rev,
+            last_edge: None,
+            last_raw: 0,
+            interval: None,
+            missed: 0,
+            rejected: 0,
+            stretched: 0,
+            stretched_missed: 0,
+            edges: 0,
+        }
+    }
+
+    // Feed the timestamp of a tacho edge.
+    // Returns the number of pulses it accounts for: 0 for a glitch, more than 1 after missing pulses
+    pub fn edge(&mut self, now: u32) -> u32 {
+        self.edges = self.edges.wrapping_add(1);
+        let raw = now.wrapping_sub(self.last_raw);
+        self.last_raw = now;
+        let last = match self.last_edge {
+            Some(last) => last,
+            None => {
+                self.last_edge = Some(now);
+                return 1;
+            }
+        };
+        let sample = now.wrapping_sub(last);
+        // a zero interval (two edges on the same tick) is no reference either, start over from this edge
+        let filtered = match self.interval {
+            Some(filtered) if filtered > 0 => filtered,
+            _ => {
+                self.interval = Some(sample);
+                self.last_edge = Some(now);
+                return 1;
+            }
+        };
+        if sample < filtered / 4 {
+            self.rejected += 1;
+            if self.rejected > RESYNC {
+                // the speed really changed, start over with the latest interval
+                self.rejected = 0;
+                self.interval = Some(raw);
+                self.last_edge = Some(now);
+                return 1;
+            }
+            // otherwise much too early, a glitch on the tacho line. Keep the last edge as reference
+            return 0;
+        }
+        self.last_edge = Some(now);
+        self.rejected = 0;
+        // an interval of about n times the filtered one means n-1 pulses went missing
+        let n = (sample + filtered / 2) / filtered;
+        let sample = if n >= 2 {
+            self.missed += n - 1;
+            self.stretched += 1;
+            self.stretched_missed += n - 1;
+            if self.stretched > RESYNC {
+                // the fan really slowed down, those pulses weren't missing
+                self.missed -= self.stretched_missed;
+                self.stretched = 0;
+                self.stretched_missed = 0;
+                self.interval = Some(sample);
+                return 1;
+            }
+            sample / n
+        } else {
+            self.stretched = 0;
+            self.stretched_missed = 0;
+            sample
+        };
+        let avg = filtered as i64 + ((sample as i64 - filtered as i64) >> FILTER_SHIFT);
+        self.interval = Some(avg as u32);
+        n.max(1)
+    }
+
+    // filtered revolution period in ticks
+    pub fn period(&self) -> Option<u32> {
+        self.interval.map(|i| i * self.pulses_per_rev)
+    }
+
+    pub fn rpm(&self) -> Option<u32> {
+        self.period()
+            .filter(|&p| p > 0)
+            .map(|p| (60 * self.tick_hz as u64 / p as u64) as u32)
+    }
+
+    pub fn missed(&self) -> u32 {
+        self.missed
+    }
+
+    // number of edges seen, tells the watchdog the tacho is alive
+    pub fn edges(&self) -> u32 {
+        self.edges
+    }
+
+    pub fn speed(&self) -> Option<Speed> {
+        Some(Speed {
+            period: self.period()?,
+            rpm: self.rpm()?,
+            missed: self.missed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HZ: u32 = 84_000_000;
+    // 50 revolutions per second, 2 pulses each
+    const PULSE: u32 = HZ / 100;
+
+    fn run(tacho: &mut Tacho, start: u32, edges: u32, interval: u32) -> u32 {
+        let mut t = start;
+        for _ in 0..edges {
+            tacho.edge(t);
+            t = t.wrapping_add(interval);
+        }
+        t
+    }
+
+    #[test]
+    fn steady_speed() {
+        let mut tacho = Tacho::new(HZ, 2);
+        assert_eq!(tacho.speed(), None);
+        run(&mut tacho, 0, 10, PULSE);
+        assert_eq!(tacho.speed(), Some(Speed { period: HZ / 50, rpm: 3000, missed: 0 }));
+    }
+
+    #[test]
+    fn counter_wraparound() {
+        let mut tacho = Tacho::new(HZ, 2);
+        run(&mut tacho, u32::MAX - 3 * PULSE, 10, PULSE);
+        assert_eq!(tacho.period(), Some(HZ / 50));
+    }
+
+    #[test]
+    fn missing_pulses_are_counted_and_bridged() {
+        let mut tacho = Tacho::new(HZ, 2);
+        let t = run(&mut tacho, 0, 10, PULSE);
+        // two pulses go missing
+        assert_eq!(tacho.edge(t + 2 * PULSE), 3);
+        let t = run(&mut tacho, t + 3 * PULSE, 4, PULSE);
+        run(&mut tacho, t + PULSE, 1, PULSE);
+        assert_eq!(tacho.missed(), 3);
+        assert_eq!(tacho.period(), Some(HZ / 50));
+    }
+
+    #[test]
+    fn glitches_are_ignored() {
+        let mut tacho = Tacho::new(HZ, 2);
+        let t = run(&mut tacho, 0, 10, PULSE);
+        assert_eq!(tacho.edge(t - PULSE + 1000), 0);
+        run(&mut tacho, t, 5, PULSE);
+        assert_eq!(tacho.period(), Some(HZ / 50));
+        assert_eq!(tacho.missed(), 0);
+    }
+
+    #[test]
+    fn follows_spin_up() {
+        let mut tacho = Tacho::new(HZ, 2);
+        let mut t = 0;
+        let mut interval = 4 * PULSE;
+        while interval > PULSE {
+            tacho.edge(t);
+            t += interval;
+            interval = interval * 9 / 10;
+        }
+        run(&mut tacho, t, 40, PULSE);
+        let period = tacho.period().unwrap();
+        assert!((period as i64 - (HZ / 50) as i64).abs() < (HZ / 5000) as i64);
+        assert_eq!(tacho.missed(), 0);
+    }
+
+    #[test]
+    fn resyncs_after_a_sudden_speed_change() {
+        let mut tacho = Tacho::new(HZ, 2);
+        let t = run(&mut tacho, 0, 10, PULSE);
+        // way shorter than the glitch threshold, but consistent
+        run(&mut tacho, t, 10, PULSE / 20);
+        assert_eq!(tacho.period(), Some(2 * (PULSE / 20)));
+    }
+
+    #[test]
+    fn follows_spin_down() {
+        let mut tacho = Tacho::new(HZ, 2);
+        let t = run(&mut tacho, 0, 10, PULSE);
+        // the speed halves at once
+        run(&mut tacho, t, 10, 2 * PULSE);
+        assert_eq!(tacho.period(), Some(2 * HZ / 50));
+        assert_eq!(tacho.missed(), 0);
+    }
+
+    #[test]
+    fn follows_a_slow_spin_down() {
+        let mut tacho = Tacho::new(HZ, 2);
+        let mut t = run(&mut tacho, 0, 10, PULSE);
+        let mut interval = PULSE;
+        while interval < 4 * PULSE {
+            tacho.edge(t);
+            t += interval;
+            interval = interval * 11 / 10;
+        }
+        run(&mut tacho, t, 40, 4 * PULSE);
+        let period = tacho.period().unwrap();
+        assert!((period as i64 - (4 * HZ / 50) as i64).abs() < (HZ / 1250) as i64, "{}", period);
+        assert_eq!(tacho.missed(), 0);
+    }
+
+    #[test]
+    fn edges_on_the_same_tick() {
+        let mut tacho = Tacho::new(HZ, 2);
+        // a zero first interval doesn't divide by zero later on
+        tacho.edge(1000);
+        tacho.edge(1000);
+        assert_eq!(tacho.edge(1000 + PULSE), 1);
+        run(&mut tacho, 1000 + 2 * PULSE, 10, PULSE);
+        assert_eq!(tacho.period(), Some(HZ / 50));
+        assert_eq!(tacho.missed(), 0);
+    }
+
+    #[test]
+    fn filters_jitter() {
+        let mut tacho = Tacho::new(HZ, 2);
+        let mut t = 0;
+        for i in 0..100 {
+            tacho.edge(t);
+            t += if i % 2 == 0 { PULSE + 8000 } else { PULSE - 8000 };
+        }
+        let period = tacho.period().unwrap();
+        assert!((period as i64 - (HZ / 50) as i64).abs() < 2 * 8000 / 8 * 2);
+    }
+}
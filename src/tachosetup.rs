@@ -0,0 +1,21 @@
+use stm32ral::{modify_reg, write_reg};
+
+pub fn tachoconfig(
+    rcc: &stm32ral::rcc::Instance,
+    syscfg: &stm32ral::syscfg::Instance,
+    exti: &stm32ral::exti::Instance,
+) {
+    // PB4 already triggers TIM3 through its alternate function,
+    // EXTI4 sees the same edges and lets us timestamp every single one
+    //Enable SYSCFG clock
+    modify_reg!(stm32ral::rcc, rcc, APB2ENR, SYSCFGEN: Enabled);
+    cortex_m::asm::dmb(); // ensure SYSCFG is powered on before we write to it
+    //connect EXTI4 to port B
+    modify_reg!(stm32ral::syscfg, syscfg, EXTICR2, EXTI4: 0b0001);
+    //rising edge like the TIM3 trigger
+    modify_reg!(stm32ral::exti, exti, RTSR, TR4: 1);
+    modify_reg!(stm32ral::exti, exti, FTSR, TR4: 0);
+    //clear a pending edge and unmask the interrupt
+    write_reg!(stm32ral::exti, exti, PR, PR4: 1);
+    modify_reg!(stm32ral::exti, exti, IMR, MR4: 1);
+}
@@ -1,4 +1,29 @@
-use stm32ral::{modify_reg, write_reg};
+use stm32ral::{modify_reg, read_reg, write_reg};
+
+use minipov::{coltiming::ColumnTiming, timing::DisplayTiming, COLS, U16PERROW};
+
+use crate::clocksetup::FREQUENCIES;
+use crate::spisetup::SPIDIV;
+use crate::util::InitError;
+
+// TIM2/3/4 run at the APB1 timer clock, SPI2 at APB1 / SPIDIV
+pub const SPI_BIT_CYCLES: u32 = FREQUENCIES.apb1_timer / FREQUENCIES.pclk1 * SPIDIV;
+
+// 128 image columns after one dark column, a column slot leaves 9 TIM2 counts
+// for latching and displaying after the column data (7 counts = 14 U16 per driver)
+pub const TIMING: DisplayTiming = DisplayTiming {
+    columns: COLS as u32,
+    blank_columns: 1,
+    words_per_column: U16PERROW as u32,
+    spi_bit_cycles: SPI_BIT_CYCLES,
+    column_counts: (U16PERROW / 2) as u32 + 9,
+};
+// fail the build if a value overflows its register or the column data doesn't fit its slot
+const _: [(); 0 - !TIMING.valid() as usize] = [];
+
+// The column period follows the fan speed, TIM2 runs at a fixed rate so the data
+// still fits the TIM4 DMA strobes, only its period and the TIM3 image window change.
+pub const COLUMN_TIMING: ColumnTiming = TIMING.column_timing();
 
 pub fn timer234debugstop(dbgmcu: &stm32ral::dbgmcu::Instance) {
     // Stop timer 2,3,4 on debug halt for better debugging
@@ -25,9 +50,9 @@ pub fn portconfig(rcc: &stm32ral::rcc::Instance, gpio: &stm32ral::gpio::Instance
     modify_reg!(stm32ral::gpio, gpio, MODER, MODER13: Alternate);
 
     //set open drain output mode for pin B0 (FET control) for the FAN
-    // drive '0' for off, high Z is on.
-    write_reg!(stm32ral::gpio, gpio, BSRR, BR0: Reset); //0 is 0 = FAN on
-    //write_reg!(stm32ral::gpio, gpio, BSRR, BS0: Set); //1 is High Z = FAN off
+    // drive '0' for on, high Z is off. fansetup takes it over for the PWM
+    //write_reg!(stm32ral::gpio, gpio, BSRR, BR0: Reset); //0 is 0 = FAN on
+    write_reg!(stm32ral::gpio, gpio, BSRR, BS0: Set); //1 is High Z = FAN off
     modify_reg!(stm32ral::gpio, gpio, PUPDR, PUPDR0: PullUp);
     modify_reg!(stm32ral::gpio, gpio, OTYPER, OT0: OpenDrain);
     modify_reg!(stm32ral::gpio, gpio, MODER, MODER0: Output);
@@ -37,19 +62,7 @@ pub fn timerconfig(
     tim2: &stm32ral::tim2::Instance,
     tim3: &stm32ral::tim3::Instance,
     tim4: &stm32ral::tim4::Instance,
-) {
-    const SPIDIV: u32 = 16;
-    const U16DIV: u32 = SPIDIV * 16;
-
-    const TIM4PERIOD: u32 = 2; //period is 2, generate 1 DMA strobe per U16 / Update event
-    const TIM4DIV: u32 = U16DIV / TIM4PERIOD; //count at double the U16 frequency
-
-    const TIM2PERIOD: u32 = 16; //16*2 = 32 U16 intervall with 28 Bytes data
-    const TIM2DIV: u32 = U16DIV * 2; //counts in 2 U16 steps
-
-    const TIM3PERIOD: u32 = 129; //128 collums image and one collumn off
-    const TIM3DIV: u32 = TIM2DIV * TIM2PERIOD; //counts image collumns
-
+) -> Result<(), InitError> {
     //Enable Timer clocks
     modify_reg!(
         stm32ral::rcc,
@@ -61,7 +74,7 @@ pub fn timerconfig(
     );
     //TIM3 configuration
     //Prescaler for TIM3
-    modify_reg!(stm32ral::tim3, tim3, PSC, PSC: TIM3DIV);
+    modify_reg!(stm32ral::tim3, tim3, PSC, PSC: TIMING.tim3_psc());
     //Onepulse mode, preload, not enabled, up
     modify_reg!(
         stm32ral::tim3,
@@ -76,6 +89,8 @@ pub fn timerconfig(
         UDIS: Enabled,
         URS: CounterOnly
     );
+    //Interrupt at the end of the image window to adapt the column timing
+    modify_reg!(stm32ral::tim3, tim3, DIER, UIE: Enabled);
     //Configure TIM3 Master mode controller to send OC2REF as TRGO
     modify_reg!(stm32ral::tim3, tim3, CR2, MMS: CompareOC2);
     //CC1 channel as input, IC1 mapped on TI1, Filtered by 8 CLK_INT
@@ -88,16 +103,16 @@ pub fn timerconfig(
     modify_reg!(stm32ral::tim3, tim3, SMCR, SMS: Trigger_Mode);
     //PWM mode 2 with preload for OC2
     modify_reg!(stm32ral::tim3, tim3, CCMR1, OC2M: 0b111, OC2PE: 1);
-    //Period 129
-    modify_reg!(stm32ral::tim3, tim3, ARR, ARR: TIM3PERIOD - 1);
+    //129 collums in TIM2 steps
+    modify_reg!(stm32ral::tim3, tim3, ARR, ARR: TIMING.tim3_arr(TIMING.column_counts, 0));
     //Delay afte 1 off, 128 on period go high
-    modify_reg!(stm32ral::tim3, tim3, CCR2, CCR: 1);
+    modify_reg!(stm32ral::tim3, tim3, CCR2, CCR: TIMING.tim3_ccr(TIMING.column_counts, 0));
     //Create an update event to auto reload the preload values
     write_reg!(stm32ral::tim3, tim3, EGR, UG: Update);
 
     //TIM2 configuration
     //Prescaler for TIM2
-    modify_reg!(stm32ral::tim2, tim2, PSC, PSC: TIM2DIV);
+    modify_reg!(stm32ral::tim2, tim2, PSC, PSC: TIMING.tim2_psc());
     // preload, not enabled, up
     modify_reg!(
         stm32ral::tim2,
@@ -120,16 +135,16 @@ pub fn timerconfig(
     modify_reg!(stm32ral::tim2, tim2, SMCR, SMS: Gated_Mode);
     //PWM mode 2 with preload for OC2
     modify_reg!(stm32ral::tim2, tim2, CCMR1, OC2M: 0b111, OC2PE: 1);
-    //Period is 16
-    modify_reg!(stm32ral::tim2, tim2, ARR, ARR: TIM2PERIOD - 1);
-    //16-9 = 7 counts high (a 4 bytes = 28 Bytes)
-    modify_reg!(stm32ral::tim2, tim2, CCR2, CCR: 9);
+    //Period is 16 with one driver
+    modify_reg!(stm32ral::tim2, tim2, ARR, ARR: TIMING.tim2_arr(TIMING.column_counts));
+    //16-9 = 7 counts high (a 4 bytes = 28 Bytes) per driver
+    modify_reg!(stm32ral::tim2, tim2, CCR2, CCR: TIMING.tim2_ccr(TIMING.column_counts));
     //Create an update event to auto reload the preload values
     write_reg!(stm32ral::tim2, tim2, EGR, UG: Update);
 
     //TIM4 configuration
     //Prescaler for TIM4
-    modify_reg!(stm32ral::tim4, tim4, PSC, PSC: TIM4DIV);
+    modify_reg!(stm32ral::tim4, tim4, PSC, PSC: TIMING.tim4_psc());
     // preload, not enabled, up
     modify_reg!(
         stm32ral::tim4,
@@ -151,9 +166,9 @@ pub fn timerconfig(
     //PWM mode 2 with preload for OC2
     modify_reg!(stm32ral::tim4, tim4, CCMR1, OC2M: 0b111, OC2PE: 1);
     //Period 0x01
-    modify_reg!(stm32ral::tim4, tim4, ARR, ARR: TIM4PERIOD - 1);
+    modify_reg!(stm32ral::tim4, tim4, ARR, ARR: TIMING.tim4_arr());
     //Delay after 1/2 period go high
-    modify_reg!(stm32ral::tim4, tim4, CCR2, CCR: 0x1);
+    modify_reg!(stm32ral::tim4, tim4, CCR2, CCR: TIMING.tim4_ccr());
     //Create an update event to auto reload the preload values
     write_reg!(stm32ral::tim4, tim4, EGR, UG: Update);
 
@@ -164,4 +179,50 @@ pub fn timerconfig(
     //Enable timer2, still inactive because gated by TIM3
     modify_reg!(stm32ral::tim2, tim2, CR1, CEN: Enabled);
     //We dont enable timer3, triggered by external pin EN set by Hardware
+
+    //Read back, without a clock the timers ignore all the writes
+    if read_reg!(stm32ral::tim2, tim2, PSC) != TIMING.tim2_psc()
+        || read_reg!(stm32ral::tim3, tim3, PSC) != TIMING.tim3_psc()
+        || read_reg!(stm32ral::tim4, tim4, PSC) != TIMING.tim4_psc()
+        || read_reg!(stm32ral::tim2, tim2, CR1, CEN == Disabled)
+        || read_reg!(stm32ral::tim4, tim4, CR1, CEN == Disabled)
+    {
+        return Err(InitError::Timer);
+    }
+    Ok(())
+}
+
+// Timer clock cycles from DWT (core clock) cycles, e.g. of the measured revolution period
+pub fn timer_cycles(cpu_cycles: u32) -> u32 {
+    (cpu_cycles as u64 * FREQUENCIES.apb1_timer as u64 / FREQUENCIES.hclk as u64) as u32
+}
+
+// Set the column period to tim2period TIM2 counts and delay the image by delay TIM2 counts.
+// Call it at the end of the image window (TIM3 update), both timers stand still then:
+// TIM3 stopped in one pulse mode and TIM2 gated off by it.
+// The update events move the preloaded values into the active registers at once,
+// so the next image starts with a consistent column timing.
+pub fn set_image_timing(tim2: &stm32ral::tim2::Instance, tim3: &stm32ral::tim3::Instance, tim2period: u32, delay: u32) {
+    let tim3start = TIMING.tim3_ccr(tim2period, delay);
+    if read_reg!(stm32ral::tim2, tim2, ARR) == TIMING.tim2_arr(tim2period)
+        && read_reg!(stm32ral::tim3, tim3, CCR2) == tim3start
+    {
+        return;
+    }
+    modify_reg!(stm32ral::tim2, tim2, ARR, ARR: TIMING.tim2_arr(tim2period));
+    modify_reg!(stm32ral::tim2, tim2, CCR2, CCR: TIMING.tim2_ccr(tim2period));
+    modify_reg!(stm32ral::tim3, tim3, ARR, ARR: TIMING.tim3_arr(tim2period, delay));
+    modify_reg!(stm32ral::tim3, tim3, CCR2, CCR: tim3start);
+    write_reg!(stm32ral::tim2, tim2, EGR, UG: Update);
+    write_reg!(stm32ral::tim3, tim3, EGR, UG: Update);
+}
+
+// Let the next tacho edge start the image window or ignore it
+pub fn arm_image_trigger(tim3: &stm32ral::tim3::Instance, armed: bool) {
+    if armed {
+        modify_reg!(stm32ral::tim3, tim3, SMCR, SMS: Trigger_Mode);
+    } else {
+        //a running window just keeps counting on the internal clock
+        modify_reg!(stm32ral::tim3, tim3, SMCR, SMS: Disabled);
+    }
 }
@@ -0,0 +1,192 @@
+// The timer chain that clocks the column data out, derived from a description of the display.
+// TIM4 strobes the DMA once per u16 word, TIM2 opens TIM4's gate for the data of a column
+// at the end of each column slot, TIM3 opens TIM2's gate for the image after the tacho edge.
+// All counts are in timer clock cycles, a prescaler value divides by PSC+1.
+// Like the original hard coded setup TIM4 divides by one prescaler step more than a word takes,
+// so a DMA strobe always comes a little after the SPI shifted out the previous word.
+// Everything is const, so a display that doesn't fit the timers fails the build:
+//   const _: [(); 0 - !TIMING.valid() as usize] = [];
+
+use crate::coltiming::ColumnTiming;
+
+// SPI words are 16 bits, TIM4 counts half words
+const WORD_BITS: u32 = 16;
+const TIM4_PERIOD: u32 = 2;
+// TIM2 counts 2 words
+const WORDS_PER_COUNT: u32 = 2;
+// timer clock cycles a DMA strobe has to come later than the end of the previous word
+pub const MIN_STROBE_SLACK: u32 = TIM4_PERIOD;
+// column slots per revolution besides the image window: a column of phase delay
+// and two spare ones to re-arm TIM3 before the next tacho edge
+const SPARE_SLOTS: u32 = 3;
+// a column slot needs some idle time after the data, the drivers latch once SCK stays idle
+pub const MIN_GAP_COUNTS: u32 = 2;
+// PSC, ARR and CCR of TIM3 and TIM4 (and the prescalers of all of them) are 16 bit
+const MAX_16BIT: u32 = 0xFFFF;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DisplayTiming {
+    // image columns
+    pub columns: u32,
+    // dark columns between the tacho edge and the image, at least 1
+    pub blank_columns: u32,
+    // u16 words sent per column (command and grayscale data of all drivers), an even number
+    pub words_per_column: u32,
+    // timer clock cycles per SPI bit
+    pub spi_bit_cycles: u32,
+    // nominal column slot length in TIM2 counts
+    pub column_counts: u32,
+}
+
+impl DisplayTiming {
+    // timer clock cycles the SPI takes for a word
+    pub const fn word_cycles(&self) -> u32 {
+        WORD_BITS * self.spi_bit_cycles
+    }
+
+    // timer clock cycles between two DMA strobes
+    pub const fn strobe_cycles(&self) -> u32 {
+        (self.tim4_psc() + 1) * (self.tim4_arr() + 1)
+    }
+
+    // timer clock cycles per TIM2 count, the strobes of 2 words
+    pub const fn count_cycles(&self) -> u32 {
+        WORDS_PER_COUNT * self.strobe_cycles()
+    }
+
+    // TIM4: one DMA strobe per word, one prescaler step slower than the SPI
+    pub const fn tim4_psc(&self) -> u32 {
+        self.word_cycles() / TIM4_PERIOD
+    }
+
+    pub const fn tim4_arr(&self) -> u32 {
+        TIM4_PERIOD - 1
+    }
+
+    // update (and DMA request) after half a word
+    pub const fn tim4_ccr(&self) -> u32 {
+        TIM4_PERIOD / 2
+    }
+
+    // TIM2: column slots, high for the data at the end of each slot
+    pub const fn tim2_psc(&self) -> u32 {
+        self.count_cycles() - 1
+    }
+
+    // TIM2 counts the column data takes
+    pub const fn data_counts(&self) -> u32 {
+        self.words_per_column / WORDS_PER_COUNT
+    }
+
+    pub const fn tim2_arr(&self, column_counts: u32) -> u32 {
+        column_counts - 1
+    }
+
+    pub const fn tim2_ccr(&self, column_counts: u32) -> u32 {
+        column_counts - self.data_counts()
+    }
+
+    // TIM3: counts in TIM2 steps, the blank columns plus a delay of up to one column, then the image
+    pub const fn tim3_psc(&self) -> u32 {
+        self.tim2_psc()
+    }
+
+    pub const fn tim3_ccr(&self, column_counts: u32, delay: u32) -> u32 {
+        self.blank_columns * column_counts + delay
+    }
+
+    pub const fn tim3_arr(&self, column_counts: u32, delay: u32) -> u32 {
+        self.tim3_ccr(column_counts, delay) + self.columns * column_counts - 1
+    }
+
+    // shortest column slot: the data plus the latch gap
+    pub const fn min_column_counts(&self) -> u32 {
+        self.data_counts() + MIN_GAP_COUNTS
+    }
+
+    // longest column slot: the whole image window with a one column delay fits into TIM3
+    pub const fn max_column_counts(&self) -> u32 {
+        (MAX_16BIT + 1) / (self.blank_columns + self.columns + 1)
+    }
+
+    // column lengths that follow the fan speed within the limits above
+    pub const fn column_timing(&self) -> ColumnTiming {
+        ColumnTiming {
+            count_cycles: self.count_cycles(),
+            slots_per_rev: self.blank_columns + self.columns + SPARE_SLOTS,
+            min_counts: self.min_column_counts(),
+            max_counts: self.max_column_counts(),
+        }
+    }
+
+    // every register value fits, the SPI keeps up with the strobes and the column data fits into its slot
+    pub const fn valid(&self) -> bool {
+        (self.columns > 0)
+            & (self.blank_columns > 0)
+            & (self.data_counts() * WORDS_PER_COUNT == self.words_per_column)
+            & (self.spi_bit_cycles > 0)
+            & (self.strobe_cycles() >= self.word_cycles() + MIN_STROBE_SLACK)
+            & (self.tim4_psc() <= MAX_16BIT)
+            & (self.tim2_psc() <= MAX_16BIT)
+            & (self.min_column_counts() <= self.column_counts)
+            & (self.column_counts <= self.max_column_counts())
+            & (self.tim3_arr(self.column_counts, self.column_counts) <= MAX_16BIT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one driver, SPI at 1/16 of the timer clock, like the original hard coded setup
+    const ONE_DRIVER: DisplayTiming = DisplayTiming {
+        columns: 128,
+        blank_columns: 1,
+        words_per_column: 14,
+        spi_bit_cycles: 16,
+        column_counts: 16,
+    };
+
+    #[test]
+    fn register_values() {
+        let t = ONE_DRIVER;
+        assert!(t.valid());
+        assert_eq!((t.tim4_psc(), t.tim4_arr(), t.tim4_ccr()), (128, 1, 1));
+        assert_eq!((t.tim2_psc(), t.tim2_arr(16), t.tim2_ccr(16)), (515, 15, 9));
+        assert_eq!((t.tim3_psc(), t.tim3_ccr(16, 0), t.tim3_arr(16, 0)), (515, 16, 129 * 16 - 1));
+        assert_eq!(t.tim3_ccr(16, 4), 20);
+    }
+
+    #[test]
+    fn a_word_takes_one_dma_strobe() {
+        let t = ONE_DRIVER;
+        let strobe = (t.tim4_psc() + 1) * (t.tim4_arr() + 1);
+        // the SPI has finished the previous word when the next one gets written
+        assert_eq!(strobe, 16 * t.spi_bit_cycles + MIN_STROBE_SLACK);
+        // TIM2 stays high for exactly the strobes of one column
+        assert_eq!(t.data_counts() * t.count_cycles(), t.words_per_column * strobe);
+    }
+
+    #[test]
+    fn data_has_to_fit_the_slot() {
+        let t = DisplayTiming { column_counts: 8, ..ONE_DRIVER };
+        assert!(!t.valid());
+        let t = DisplayTiming { words_per_column: 4 * 14, column_counts: 30, ..ONE_DRIVER };
+        assert!(t.valid());
+        let t = DisplayTiming { words_per_column: 4 * 14, column_counts: 29, ..ONE_DRIVER };
+        assert!(!t.valid());
+    }
+
+    #[test]
+    fn values_have_to_fit_16_bits() {
+        let t = DisplayTiming { column_counts: 600, ..ONE_DRIVER };
+        assert!(!t.valid());
+        assert_eq!(ONE_DRIVER.max_column_counts(), 0x10000 / 130);
+        let t = DisplayTiming { spi_bit_cycles: 4096, ..ONE_DRIVER };
+        assert!(!t.valid());
+        let t = DisplayTiming { blank_columns: 0, ..ONE_DRIVER };
+        assert!(!t.valid());
+        let t = DisplayTiming { words_per_column: 15, ..ONE_DRIVER };
+        assert!(!t.valid());
+    }
+}
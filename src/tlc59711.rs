@@ -0,0 +1,183 @@
+// Command word of the TLC59711 12 channel PWM led driver.
+// Each driver shifts in 224 bits MSB first, the first 32 bits are
+//   31..26 write command 0x25
+//   25 OUTTMG, 24 EXTGCK, 23 TMGRST, 22 DSPRPT, 21 BLANK
+//   20..14 BCB, 13..7 BCG, 6..0 BCR (7 bit global brightness per colour group)
+// followed by 12 16 bit grayscale values.
+// We send 16 bit SPI frames so the command ends up in two u16 words.
+
+// the three global brightness groups, each drives 4 outputs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorGroup {
+    Red,
+    Green,
+    Blue,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tlc59711Command {
+    // latch on the rising (true) or falling (false) SCKI edge
+    pub outtmg: bool,
+    // use SCKI (true) instead of the internal oscillator as grayscale clock
+    pub extclk: bool,
+    // reset the grayscale counter when new data gets latched
+    pub tmgrst: bool,
+    // repeat the PWM period (auto display repeat)
+    pub dsprpt: bool,
+    // switch all outputs off
+    pub blank: bool,
+    // global brightness of the red, green and blue output groups, 0..=127
+    pub bcr: u8,
+    pub bcg: u8,
+    pub bcb: u8,
+}
+
+impl Tlc59711Command {
+    pub const WRITE: u32 = 0x25;
+    pub const BC_MAX: u8 = 0x7F;
+
+    //CMD 0x25, OUTTMG=0, EXTCLK=0,TMGRST=1, DSPRPT=0, BLANK=0, BC=1FFFFF
+    // restart the PWM with every column and run it only once per column
+    pub const DEFAULT: Tlc59711Command = Tlc59711Command {
+        outtmg: false,
+        extclk: false,
+        tmgrst: true,
+        dsprpt: false,
+        blank: false,
+        bcr: Tlc59711Command::BC_MAX,
+        bcg: Tlc59711Command::BC_MAX,
+        bcb: Tlc59711Command::BC_MAX,
+    };
+
+    pub const fn bits(&self) -> u32 {
+        Tlc59711Command::WRITE << 26
+            | (self.outtmg as u32) << 25
+            | (self.extclk as u32) << 24
+            | (self.tmgrst as u32) << 23
+            | (self.dsprpt as u32) << 22
+            | (self.blank as u32) << 21
+            | (self.bcb as u32 & 0x7F) << 14
+            | (self.bcg as u32 & 0x7F) << 7
+            | (self.bcr as u32 & 0x7F)
+    }
+
+    // the two leading u16 words of a column, high word first
+    pub const fn words(&self) -> [u16; 2] {
+        let bits = self.bits();
+        [(bits >> 16) as u16, bits as u16]
+    }
+
+    // set the global brightness of one group, values above BC_MAX get clamped
+    pub fn set_brightness(&mut self, group: ColorGroup, bc: u8) {
+        let bc = bc.min(Tlc59711Command::BC_MAX);
+        match group {
+            ColorGroup::Red => self.bcr = bc,
+            ColorGroup::Green => self.bcg = bc,
+            ColorGroup::Blue => self.bcb = bc,
+        }
+    }
+
+    pub fn set_brightness_all(&mut self, bc: u8) {
+        self.set_brightness(ColorGroup::Red, bc);
+        self.set_brightness(ColorGroup::Green, bc);
+        self.set_brightness(ColorGroup::Blue, bc);
+    }
+
+    pub fn brightness(&self, group: ColorGroup) -> u8 {
+        match group {
+            ColorGroup::Red => self.bcr,
+            ColorGroup::Green => self.bcg,
+            ColorGroup::Blue => self.bcb,
+        }
+    }
+
+    // decode the leading words of a column, None if it doesn't hold a write command
+    pub fn from_words(words: [u16; 2]) -> Option<Tlc59711Command> {
+        let bits = (words[0] as u32) << 16 | words[1] as u32;
+        if bits >> 26 != Tlc59711Command::WRITE {
+            return None;
+        }
+        let flag = |bit: u32| bits & (1 << bit) != 0;
+        Some(Tlc59711Command {
+            outtmg: flag(25),
+            extclk: flag(24),
+            tmgrst: flag(23),
+            dsprpt: flag(22),
+            blank: flag(21),
+            bcb: (bits >> 14) as u8 & 0x7F,
+            bcg: (bits >> 7) as u8 & 0x7F,
+            bcr: bits as u8 & 0x7F,
+        })
+    }
+}
+
+impl Default for Tlc59711Command {
+    fn default() -> Self {
+        Tlc59711Command::DEFAULT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OFF: Tlc59711Command = Tlc59711Command {
+        outtmg: false,
+        extclk: false,
+        tmgrst: false,
+        dsprpt: false,
+        blank: false,
+        bcr: 0,
+        bcg: 0,
+        bcb: 0,
+    };
+
+    #[test]
+    fn default_matches_the_old_ledcmd() {
+        assert_eq!(Tlc59711Command::DEFAULT.words(), [0x949F, 0xFFFF]);
+    }
+
+    #[test]
+    fn display_repeat_variant() {
+        let cmd = Tlc59711Command { tmgrst: false, dsprpt: true, ..Tlc59711Command::DEFAULT };
+        assert_eq!(cmd.words(), [0x945F, 0xFFFF]);
+    }
+
+    #[test]
+    fn datasheet_bit_positions() {
+        assert_eq!(OFF.bits(), 0x25 << 26);
+        assert_eq!(Tlc59711Command { outtmg: true, ..OFF }.bits(), 0x25 << 26 | 1 << 25);
+        assert_eq!(Tlc59711Command { extclk: true, ..OFF }.bits(), 0x25 << 26 | 1 << 24);
+        assert_eq!(Tlc59711Command { tmgrst: true, ..OFF }.bits(), 0x25 << 26 | 1 << 23);
+        assert_eq!(Tlc59711Command { dsprpt: true, ..OFF }.bits(), 0x25 << 26 | 1 << 22);
+        assert_eq!(Tlc59711Command { blank: true, ..OFF }.bits(), 0x25 << 26 | 1 << 21);
+        assert_eq!(Tlc59711Command { bcb: 0x7F, ..OFF }.bits(), 0x25 << 26 | 0x7F << 14);
+        assert_eq!(Tlc59711Command { bcg: 0x7F, ..OFF }.bits(), 0x25 << 26 | 0x7F << 7);
+        assert_eq!(Tlc59711Command { bcr: 0x7F, ..OFF }.bits(), 0x25 << 26 | 0x7F);
+    }
+
+    #[test]
+    fn brightness_is_limited_to_7_bits() {
+        let cmd = Tlc59711Command { bcr: 0xFF, ..OFF };
+        assert_eq!(cmd.bits(), Tlc59711Command { bcr: 0x7F, ..OFF }.bits());
+    }
+
+    #[test]
+    fn set_brightness_per_group_and_all() {
+        let mut cmd = Tlc59711Command::DEFAULT;
+        cmd.set_brightness(ColorGroup::Green, 0x20);
+        assert_eq!(cmd.words(), [0x949F, 0xD07F]);
+        cmd.set_brightness_all(200);
+        assert_eq!(cmd, Tlc59711Command::DEFAULT);
+        cmd.set_brightness_all(0);
+        assert_eq!(cmd.words(), [0x9480, 0x0000]);
+        assert_eq!(cmd.brightness(ColorGroup::Blue), 0);
+    }
+
+    #[test]
+    fn words_round_trip() {
+        let cmd = Tlc59711Command { blank: true, bcr: 1, bcg: 0x40, bcb: 0x15, ..Tlc59711Command::DEFAULT };
+        assert_eq!(Tlc59711Command::from_words(cmd.words()), Some(cmd));
+        assert_eq!(Tlc59711Command::from_words([0, 0]), None);
+    }
+}
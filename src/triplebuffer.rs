@@ -0,0 +1,382 @@
+// Three buffers passed between the renderer (idle task) and the DMA transfer complete interrupt.
+// The renderer gets a &mut guard for a free buffer and submits it when it's done,
+// the DMA side only ever sees raw addresses and picks the submitted buffers up in order.
+// The DMA owns one or two buffers (the active one and the idle target), they never go out as a guard.
+// Both sides use the same TripleBuffer, share it as a resource and lock it in the idle task.
+// Every buffer keeps the image phase it was rendered with, the DMA side applies its fraction
+// when the buffer gets sent, so the whole columns and the fraction always belong together.
+
+use core::ops::{Deref, DerefMut};
+
+use crate::handoff;
+use crate::phase::Phase;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Owner {
+    // free for the renderer
+    Free,
+    // a guard is out
+    Rendering,
+    // submitted, waiting for the DMA, the number orders the submissions
+    Ready(u32),
+    // active or idle target of the DMA
+    Dma,
+}
+
+pub struct TripleBuffer<T: 'static> {
+    bufs: [*mut T; 3],
+    owners: [Owner; 3],
+    phases: [Phase; 3],
+    submitted: u32,
+}
+
+// Safety: the buffers are 'static and the owners make sure only one side touches each of them
+unsafe impl<T: Send> Send for TripleBuffer<T> {}
+
+// Exclusive access to a buffer while rendering, hand it back with submit
+pub struct Guard<T: 'static> {
+    buf: &'static mut T,
+    index: usize,
+}
+
+impl<T> Deref for Guard<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.buf
+    }
+}
+
+impl<T> DerefMut for Guard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.buf
+    }
+}
+
+// Result of a transfer complete: the address to program as the next DMA target,
+// and the buffer behind it if it's a freshly submitted one (the DMA doesn't read it yet)
+pub struct Scheduled<'a, T> {
+    pub target: u32,
+    pub fresh: Option<&'a mut T>,
+}
+
+impl<T> TripleBuffer<T> {
+    // The first two buffers start out as the DMA targets, the third is free for the renderer
+    pub fn new(storage: &'static mut [T; 3]) -> Self {
+        let (a, rest) = storage.split_at_mut(1);
+        let (b, c) = rest.split_at_mut(1);
+        TripleBuffer {
+            bufs: [&mut a[0] as *mut T, &mut b[0] as *mut T, &mut c[0] as *mut T],
+            owners: [Owner::Dma, Owner::Dma, Owner::Free],
+            phases: [Phase::default(); 3],
+            submitted: 0,
+        }
+    }
+
+    // memory 0 and memory 1 of the DMA stream
+    pub fn dma_targets(&self) -> (u32, u32) {
+        (self.address(0), self.address(1))
+    }
+
+    pub fn owner(&self, index: usize) -> Owner {
+        self.owners[index]
+    }
+
+    pub fn address(&self, index: usize) -> u32 {
+        self.bufs[index] as u32
+    }
+
+    // submitted buffers waiting for the DMA
+    pub fn queued(&self) -> u32 {
+        self.owners.iter().filter(|owner| matches!(owner, Owner::Ready(_))).count() as u32
+    }
+
+    // phase the buffer at address was rendered with
+    pub fn phase_of(&self, address: u32) -> Phase {
+        self.phases[self.index_of(address)]
+    }
+
+    fn index_of(&self, address: u32) -> usize {
+        let index = (0..3).find(|&i| self.address(i) == address);
+        index.expect("not one of the triple buffers")
+    }
+
+    // A free buffer to render into, None while the DMA and the queue hold the others
+    pub fn acquire(&mut self) -> Option<Guard<T>> {
+        let index = (0..3).find(|&i| self.owners[i] == Owner::Free)?;
+        self.owners[index] = Owner::Rendering;
+        // Safety: the buffer was free, nothing else has a reference to it until it gets submitted
+        let buf = unsafe { &mut *self.bufs[index] };
+        Some(Guard { buf, index })
+    }
+
+    // Queue a buffer for the DMA, rendered with the whole columns of phase
+    pub fn submit(&mut self, guard: Guard<T>, phase: Phase) {
+        assert!(core::ptr::eq(self.bufs[guard.index], guard.buf), "guard of another triple buffer");
+        self.phases[guard.index] = phase;
+        self.owners[guard.index] = Owner::Ready(self.submitted);
+        self.submitted = self.submitted.wrapping_add(1);
+    }
+
+    // The DMA finished the buffer at finished and switched to active.
+    // Hands finished back to the renderer unless it's the active one again
+    // and picks the oldest submitted buffer as the next target, without one active gets repeated.
+    pub fn transfer_complete(&mut self, finished: u32, active: u32) -> Scheduled<'_, T> {
+        let submitted = self.submitted;
+        let next = (0..3)
+            .filter_map(|i| match self.owners[i] {
+                Owner::Ready(n) => Some((submitted.wrapping_sub(n), i)),
+                _ => None,
+            })
+            .max()
+            .map(|(_, i)| i);
+        let handoff = handoff::schedule(finished, active, next.map(|i| self.address(i)));
+        if let Some(release) = handoff.release {
+            let index = self.index_of(release);
+            debug_assert_eq!(self.owners[index], Owner::Dma);
+            self.owners[index] = Owner::Free;
+        }
+        let fresh = next.map(|i| {
+            self.owners[i] = Owner::Dma;
+            // Safety: submitted buffers belong to the DMA side, the DMA starts reading it after the current transfer
+            unsafe { &mut *self.bufs[i] }
+        });
+        Scheduled {
+            target: handoff.idle_target,
+            fresh,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn storage() -> &'static mut [u32; 3] {
+        Box::leak(Box::new([0; 3]))
+    }
+
+    // The double buffered DMA stream: two address registers and the active one
+    struct Dma {
+        targets: [u32; 2],
+        active: usize,
+    }
+
+    impl Dma {
+        // switch targets at the end of a transfer, program the new idle target
+        fn complete(&mut self, tb: &mut TripleBuffer<u32>) -> Option<u32> {
+            let finished = self.targets[self.active];
+            self.active = 1 - self.active;
+            let scheduled = tb.transfer_complete(finished, self.targets[self.active]);
+            let frame = scheduled.fresh.map(|buf| *buf);
+            self.targets[1 - self.active] = scheduled.target;
+            frame
+        }
+
+        // frame in the buffer the DMA reads right now
+        fn reading(&self, tb: &TripleBuffer<u32>) -> u32 {
+            unsafe { *tb.bufs[tb.index_of(self.targets[self.active])] }
+        }
+    }
+
+    #[test]
+    fn starts_with_two_dma_targets() {
+        let mut tb = TripleBuffer::new(storage());
+        let (a, b) = tb.dma_targets();
+        assert_ne!(a, b);
+        let guard = tb.acquire().unwrap();
+        assert_eq!(&*guard as *const u32 as u32, tb.address(2));
+        assert!(tb.acquire().is_none());
+    }
+
+    #[test]
+    fn frames_get_displayed_in_order() {
+        let mut tb = TripleBuffer::new(storage());
+        let (a, b) = tb.dma_targets();
+        let mut dma = Dma { targets: [a, b], active: 0 };
+        let mut displayed = Vec::new();
+        for frame in 1..10 {
+            let mut guard = tb.acquire().unwrap();
+            *guard = frame;
+            tb.submit(guard, Phase::default());
+            if let Some(frame) = dma.complete(&mut tb) {
+                displayed.push(frame);
+            }
+        }
+        assert_eq!(displayed, (1..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn underrun_repeats_the_active_buffer() {
+        let mut tb = TripleBuffer::new(storage());
+        let (a, b) = tb.dma_targets();
+        let mut dma = Dma { targets: [a, b], active: 0 };
+        assert_eq!(dma.complete(&mut tb), None);
+        // a got released, b repeated
+        assert_eq!(dma.targets, [b, b]);
+        assert_eq!(tb.owner(0), Owner::Free);
+        assert_eq!(dma.complete(&mut tb), None);
+        assert_eq!(dma.targets, [b, b]);
+        // two free buffers for the renderer now, both get queued
+        let mut first = tb.acquire().unwrap();
+        let mut second = tb.acquire().unwrap();
+        *first = 1;
+        *second = 2;
+        tb.submit(second, Phase::default());
+        tb.submit(first, Phase::default());
+        assert_eq!(dma.complete(&mut tb), Some(2));
+        assert_eq!(dma.reading(&tb), 0);
+        assert_eq!(dma.complete(&mut tb), Some(1));
+        assert_eq!(dma.reading(&tb), 2);
+    }
+
+    // The renderer runs a frame ahead of the DMA while the rotation crosses column boundaries.
+    // Whole columns (drawn into the buffer) and the fraction (window delay) of the buffer being sent
+    // come from the same phase, so the image never jumps back.
+    #[test]
+    fn phase_stays_with_the_buffer() {
+        use crate::phase::{Rotation, SUBCOLS};
+        let mut tb = TripleBuffer::new(storage());
+        let (a, b) = tb.dma_targets();
+        let mut dma = Dma { targets: [a, b], active: 0 };
+        let mut rotation = Rotation::new(Phase::new(0), SUBCOLS as i32 / 3);
+        let mut last = 0;
+        for _ in 0..20 {
+            if let Some(mut guard) = tb.acquire() {
+                let phase = rotation.phase();
+                // the columns the image got shifted by when rendering
+                *guard = phase.cols() as u32;
+                tb.submit(guard, phase);
+            }
+            dma.complete(&mut tb);
+            rotation.revolution();
+            let active = dma.targets[dma.active];
+            let shown = dma.reading(&tb) * SUBCOLS + tb.phase_of(active).subcols() % SUBCOLS;
+            assert!(shown >= last, "{} after {}", shown, last);
+            assert_eq!(shown, tb.phase_of(active).subcols());
+            last = shown;
+        }
+        assert!(last > 4 * SUBCOLS);
+    }
+
+    // A transfer error stops the stream, stopping it sets TCIF and the interrupt comes again.
+    // That one must not hand a buffer back or count a frame, the DMA still owns both targets.
+    #[test]
+    fn spurious_complete_after_an_error() {
+        use crate::dmaflags::{Event, TCIF, TEIF};
+        use crate::framestats::FrameStats;
+        let mut tb = TripleBuffer::new(storage());
+        let (a, b) = tb.dma_targets();
+        let mut dma = Dma { targets: [a, b], active: 0 };
+        let mut stats = FrameStats::new();
+        let mut stopped = false;
+        let mut frame = 0;
+        // what dma_handler does with the flags of an interrupt
+        fn interrupt(
+            tb: &mut TripleBuffer<u32>,
+            dma: &mut Dma,
+            stats: &mut FrameStats,
+            stopped: &mut bool,
+            flags: u32,
+        ) -> Event {
+            let event = stats.dma_interrupt(0, flags, *stopped);
+            match event {
+                Event::Complete => {
+                    let fresh = dma.complete(tb);
+                    stats.transfer(0, fresh.is_some());
+                }
+                Event::Error => *stopped = true,
+                Event::Ignore => (),
+            }
+            event
+        }
+        for _ in 0..2 {
+            let mut guard = tb.acquire().unwrap();
+            frame += 1;
+            *guard = frame;
+            tb.submit(guard, Phase::default());
+            interrupt(&mut tb, &mut dma, &mut stats, &mut stopped, TCIF);
+        }
+        let owners = tb.owners;
+        let before = stats;
+        assert_eq!(interrupt(&mut tb, &mut dma, &mut stats, &mut stopped, TEIF), Event::Error);
+        assert_eq!(interrupt(&mut tb, &mut dma, &mut stats, &mut stopped, TCIF), Event::Ignore);
+        assert_eq!(tb.owners, owners);
+        assert_eq!(
+            (stats.displayed, stats.repeated, stats.dma.complete),
+            (before.displayed, before.repeated, before.dma.complete)
+        );
+        assert_eq!(stats.dma.transfer_errors, 1);
+        // restarted, the frames keep coming in order
+        stopped = false;
+        let mut guard = tb.acquire().unwrap();
+        *guard = frame + 1;
+        tb.submit(guard, Phase::default());
+        assert_eq!(interrupt(&mut tb, &mut dma, &mut stats, &mut stopped, TCIF), Event::Complete);
+        assert_eq!(dma.reading(&tb), frame);
+        assert_eq!(stats.displayed, before.displayed + 1);
+    }
+
+    #[derive(Clone, Copy, Debug)]
+    enum Op {
+        // transfer complete interrupt, it preempts the idle task between its locked sections
+        Complete,
+        Acquire,
+        Submit,
+    }
+
+    // Every interleaving of the interrupt with the renderer:
+    // each buffer has exactly one owner, the DMA owns exactly its targets,
+    // a guard never points at a DMA target, frames come out in submission order and none gets dropped.
+    #[test]
+    fn invariants_hold_for_all_interleavings() {
+        use crate::framestats::FrameStats;
+        const OPS: [Op; 3] = [Op::Complete, Op::Acquire, Op::Submit];
+        const STEPS: u32 = 9;
+        for mut seq in 0..3usize.pow(STEPS) {
+            let mut tb = TripleBuffer::new(storage());
+            let (a, b) = tb.dma_targets();
+            let mut dma = Dma { targets: [a, b], active: 0 };
+            let mut guards: Vec<Guard<u32>> = Vec::new();
+            let mut stats = FrameStats::new();
+            let mut frame = 0;
+            let mut last = 0;
+            for _ in 0..STEPS {
+                let op = OPS[seq % 3];
+                seq /= 3;
+                match op {
+                    Op::Complete => {
+                        let shown = dma.complete(&mut tb);
+                        stats.transfer(0, shown.is_some());
+                        if let Some(shown) = shown {
+                            assert!(shown > last, "{:?}", op);
+                            last = shown;
+                        }
+                    }
+                    Op::Acquire => {
+                        if let Some(mut guard) = tb.acquire() {
+                            frame += 1;
+                            *guard = frame;
+                            guards.push(guard);
+                        }
+                    }
+                    Op::Submit => {
+                        if !guards.is_empty() {
+                            tb.submit(guards.remove(0), Phase::default());
+                            stats.submit(0, 0);
+                        }
+                    }
+                }
+                for i in 0..3 {
+                    let address = tb.address(i);
+                    let targeted = dma.targets.contains(&address);
+                    let guarded = guards.iter().any(|g| &*g.buf as *const u32 as u32 == address);
+                    assert!(!(targeted && guarded));
+                    assert_eq!(targeted, tb.owner(i) == Owner::Dma);
+                    assert_eq!(guarded, tb.owner(i) == Owner::Rendering);
+                }
+                assert_eq!(stats.dropped(tb.queued()), 0);
+            }
+        }
+    }
+}
@@ -9,3 +9,43 @@ macro_rules! block_until {
         block_while!(!$condition)
     };
 }
+
+// Variants that give up after timeout DWT cycles, they evaluate to Err(Timeout) then.
+// The cycle counter has to be running, it counts at whatever the core clock is at the moment.
+macro_rules! block_while_timeout {
+    ($timeout:expr, $condition:expr) => {{
+        let start = cortex_m::peripheral::DWT::get_cycle_count();
+        loop {
+            if !$condition {
+                break Ok(());
+            }
+            if cortex_m::peripheral::DWT::get_cycle_count().wrapping_sub(start) >= $timeout {
+                break Err(crate::util::Timeout);
+            }
+        }
+    }};
+}
+
+macro_rules! block_until_timeout {
+    ($timeout:expr, $condition:expr) => {
+        block_while_timeout!($timeout, !$condition)
+    };
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timeout;
+
+// What went wrong while setting up the hardware in init
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InitError {
+    // the internal oscillator didn't start
+    Hsi,
+    // the PLL didn't stop or lock
+    Pll,
+    // the core clock didn't switch to the HSI or the PLL
+    ClockSwitch,
+    // the DMA stream didn't get disabled or enabled
+    DmaEnable,
+    // the timer registers didn't take the configuration
+    Timer,
+}
@@ -0,0 +1,131 @@
+// Watchdog on the tacho edges.
+// A jammed fan or a loose tacho wire stops the image triggers and the drivers keep the
+// last column lit, so no edges for too long while the fan should turn is a fault.
+// It's latched, only a reset clears it.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    // not a single edge since the fan got started, tacho wire off?
+    NoTacho,
+    // the edges stopped, the fan is jammed
+    Stall,
+}
+
+pub struct Watchdog {
+    tick_hz: u32,
+    // time for the first edge after the fan got started
+    start_ms: u32,
+    // max. time between two edges
+    stall_ms: u32,
+    armed: bool,
+    // edge count (see Tacho::edges) of the last check and when it changed
+    edges: u32,
+    last_seen: u32,
+    seen_edge: bool,
+    fault: Option<Fault>,
+}
+
+impl Watchdog {
+    pub const fn new(tick_hz: u32, start_ms: u32, stall_ms: u32) -> Self {
+        Watchdog {
+            tick_hz,
+            start_ms,
+            stall_ms,
+            armed: false,
+            edges: 0,
+            last_seen: 0,
+            seen_edge: false,
+            fault: None,
+        }
+    }
+
+    // the fan got started, expect edges from now on
+    pub fn arm(&mut self, now: u32, edges: u32) {
+        if !self.armed {
+            self.armed = true;
+            self.edges = edges;
+            self.last_seen = now;
+            self.seen_edge = false;
+        }
+    }
+
+    // the fan got switched off
+    pub fn disarm(&mut self) {
+        self.armed = false;
+    }
+
+    // Check the edge count at timestamp now (wrapping tick counter),
+    // call it more often than the timeouts
+    pub fn check(&mut self, now: u32, edges: u32) -> Option<Fault> {
+        if self.fault.is_some() || !self.armed {
+            return self.fault;
+        }
+        if edges != self.edges {
+            self.edges = edges;
+            self.last_seen = now;
+            self.seen_edge = true;
+        }
+        let (limit, fault) = if self.seen_edge {
+            (self.stall_ms, Fault::Stall)
+        } else {
+            (self.start_ms, Fault::NoTacho)
+        };
+        let elapsed = now.wrapping_sub(self.last_seen) as u64;
+        if elapsed > limit as u64 * self.tick_hz as u64 / 1000 {
+            self.fault = Some(fault);
+        }
+        self.fault
+    }
+
+    pub fn fault(&self) -> Option<Fault> {
+        self.fault
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HZ: u32 = 1000;
+
+    #[test]
+    fn quiet_while_disarmed() {
+        let mut wd = Watchdog::new(HZ, 3000, 250);
+        assert_eq!(wd.check(100_000, 0), None);
+        wd.arm(0, 0);
+        wd.disarm();
+        assert_eq!(wd.check(100_000, 0), None);
+    }
+
+    #[test]
+    fn no_edge_after_start() {
+        let mut wd = Watchdog::new(HZ, 3000, 250);
+        wd.arm(1000, 7);
+        assert_eq!(wd.check(3900, 7), None);
+        assert_eq!(wd.check(4001, 7), Some(Fault::NoTacho));
+    }
+
+    #[test]
+    fn edges_keep_it_quiet_until_they_stop() {
+        let mut wd = Watchdog::new(HZ, 3000, 250);
+        wd.arm(0, 0);
+        let mut edges = 0;
+        for t in (500..5000).step_by(10) {
+            edges += 1;
+            assert_eq!(wd.check(t, edges), None);
+        }
+        assert_eq!(wd.check(5240, edges), None);
+        assert_eq!(wd.check(5260, edges), Some(Fault::Stall));
+    }
+
+    #[test]
+    fn fault_is_latched() {
+        let mut wd = Watchdog::new(HZ, 3000, 250);
+        wd.arm(u32::MAX - 100, 0);
+        assert_eq!(wd.check(3000, 0), Some(Fault::NoTacho));
+        wd.disarm();
+        wd.arm(3000, 0);
+        assert_eq!(wd.check(3001, 1), Some(Fault::NoTacho));
+        assert_eq!(wd.fault(), Some(Fault::NoTacho));
+    }
+}